@@ -37,10 +37,17 @@ if [ -f "$IMG_FILE" ]; then
   ui_print "- Existing modules.img found, keeping it."
 else
   ui_print "- Creating 2G modules.img (ext4, no journal)..."
-  truncate -s 2G "$IMG_FILE"
+  # Some busybox truncate applets reject size suffixes, pass plain bytes
+  IMG_SIZE=$((2 * 1024 * 1024 * 1024))
+  if ! truncate -s "$IMG_SIZE" "$IMG_FILE"; then
+    dd if=/dev/zero of="$IMG_FILE" bs=1 count=0 seek="$IMG_SIZE" 2>/dev/null
+  fi
   if ! mkfs.ext4 -O ^has_journal "$IMG_FILE"; then
-    ui_print "! Failed to format modules.img using system mkfs.ext4"
-    rm -f "$IMG_FILE"
+    ui_print "! mkfs.ext4 rejected -O ^has_journal, formatting with journal"
+    if ! mkfs.ext4 "$IMG_FILE"; then
+      ui_print "! Failed to format modules.img using system mkfs.ext4"
+      rm -f "$IMG_FILE"
+    fi
   fi
 fi
 
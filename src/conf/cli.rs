@@ -5,7 +5,7 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
-use super::config::CONFIG_FILE_DEFAULT;
+use super::config::{CONFIG_FILE_DEFAULT, LogFormat, OverlayMode};
 
 #[derive(Parser, Debug)]
 #[command(name = "meta-hybrid", version, about = "Hybrid Mount Metamodule")]
@@ -22,6 +22,20 @@ pub struct Cli {
     pub partitions: Vec<String>,
     #[arg(long = "dry-run")]
     pub dry_run: bool,
+    /// Override the storage backend for this run only; the config file is left untouched
+    #[arg(long = "storage-backend", value_enum)]
+    pub storage_backend: Option<OverlayMode>,
+    #[arg(long = "log-format", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Only consider these module ids, for bisecting conflicts without disabling modules
+    #[arg(long = "only", value_delimiter = ',', global = true)]
+    pub only: Vec<String>,
+    /// Leave these module ids out of the scan
+    #[arg(long = "except", value_delimiter = ',', global = true)]
+    pub except: Vec<String>,
+    /// Only mount these partitions, for finding the one whose mount breaks the boot
+    #[arg(long = "mount-only-partitions", value_delimiter = ',', global = true)]
+    pub mount_only_partitions: Vec<String>,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -31,8 +45,14 @@ pub enum Commands {
     GenConfig {
         #[arg(short = 'o', long = "output", default_value = CONFIG_FILE_DEFAULT)]
         output: PathBuf,
+        /// Write the bare serialized defaults without comments
+        #[arg(long)]
+        minimal: bool,
     },
     ShowConfig,
+    /// Print every config field with its type, default and help text as JSON
+    #[command(name = "config-schema")]
+    ConfigSchema,
     #[command(name = "save-config")]
     SaveConfig {
         #[arg(long)]
@@ -44,16 +64,104 @@ pub enum Commands {
         module: String,
         #[arg(long)]
         payload: String,
+        /// Apply only the given default_mode/paths to the saved rules, a null path removes it
+        #[arg(long)]
+        merge: bool,
     },
     Storage,
+    Status,
     Modules,
-    Conflicts,
+    Toggle {
+        #[arg(long)]
+        module: String,
+        #[arg(long)]
+        enable: bool,
+        #[arg(long)]
+        preview: bool,
+    },
+    #[command(name = "disable-all")]
+    DisableAll {
+        #[arg(long)]
+        confirm: bool,
+    },
+    #[command(name = "enable-all")]
+    EnableAll {
+        #[arg(long)]
+        confirm: bool,
+    },
+    #[command(name = "check-updates")]
+    CheckUpdates,
+    Conflicts {
+        #[arg(long = "simulate-rule")]
+        simulate_rule: Vec<String>,
+    },
     Diagnostics,
+    /// Re-plan and print conflicts and diagnostics whenever module contents change (never mounts)
+    Watch {
+        #[arg(long, default_value_t = 500)]
+        debounce_ms: u64,
+    },
+    #[command(name = "export-mounts")]
+    ExportMounts,
+    Tree {
+        #[arg(long)]
+        module: Vec<String>,
+    },
+    Benchmark,
+    Diff {
+        #[arg(long)]
+        snapshot: String,
+        #[arg(long)]
+        save: bool,
+    },
+    Log {
+        #[arg(short = 'n', long, default_value_t = 200)]
+        lines: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    Prop {
+        #[arg(long)]
+        module: String,
+        #[arg(long)]
+        key: String,
+        #[arg(long)]
+        value: Option<String>,
+    },
+    Export {
+        #[arg(short = 'o', long = "output")]
+        output: PathBuf,
+    },
+    Import {
+        #[arg(short = 'i', long = "input")]
+        input: PathBuf,
+    },
+    /// Find corrupt config, state and rules files, and with --confirm back them up and rebuild them
+    Repair {
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Unmount and wipe the module storage, runtime state and Ratoon counter to start fresh
+    Clean {
+        #[arg(long)]
+        keep_silos: bool,
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Nuke the ext4 sysfs entry of a mount through the KSU driver (troubleshooting only)
+    Nuke {
+        #[arg(long)]
+        target: String,
+        #[arg(long)]
+        confirm: bool,
+    },
     #[command(name = "system-action")]
     SystemAction {
         #[arg(long)]
         action: String,
         #[arg(long)]
         value: Option<String>,
+        #[arg(long)]
+        confirm: bool,
     },
 }
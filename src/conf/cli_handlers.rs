@@ -1,20 +1,88 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Context, Result, bail};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     conf::{
         cli::Cli,
         config::{CONFIG_FILE_DEFAULT, Config},
     },
-    core::{executor, granary, inventory, modules, planner, storage, winnow},
-    utils,
+    core::{executor, granary, inventory, modules, planner, storage, watch, winnow},
+    defs,
+    error::MountError,
+    mount::magic,
+    try_umount, utils,
 };
 
+const CONFIG_BUNDLE_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct PlanPreviewJson {
+    overlay_modules: Vec<String>,
+    magic_modules: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ActionResultJson<'a> {
+    action: &'a str,
+    success: bool,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct BenchmarkPhaseJson {
+    phase: &'static str,
+    millis: f64,
+}
+
+#[derive(Serialize)]
+struct BenchmarkJson {
+    modules: usize,
+    overlay_modules: usize,
+    magic_modules: usize,
+    phases: Vec<BenchmarkPhaseJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConfigBundle {
+    version: u32,
+    exported_at: u64,
+    // Winnowing rules travel inside the config itself
+    config: Config,
+    #[serde(default)]
+    rules: BTreeMap<String, inventory::ModuleRules>,
+}
+
+#[derive(Serialize)]
+struct RatoonJson {
+    count: u8,
+    threshold: u8,
+}
+
+#[derive(Serialize)]
+struct WinnowRuleJson {
+    path: String,
+    module_id: String,
+    matched: bool,
+}
+
+#[derive(Serialize)]
+struct StatusJson {
+    storage: storage::StorageStatus,
+    modules: Vec<modules::ModuleInfo>,
+    silo_count: usize,
+    ratoon_count: u8,
+}
+
 #[derive(Serialize)]
 struct DiagnosticIssueJson {
     level: String,
@@ -22,7 +90,52 @@ struct DiagnosticIssueJson {
     message: String,
 }
 
+#[derive(Serialize)]
+struct WatchReportJson {
+    timestamp: u64,
+    modules: usize,
+    conflicts: Vec<winnow::ChaffConflict>,
+    diagnostics: Vec<DiagnosticIssueJson>,
+}
+
+impl From<executor::DiagnosticIssue> for DiagnosticIssueJson {
+    fn from(issue: executor::DiagnosticIssue) -> Self {
+        Self {
+            level: match issue.level {
+                executor::DiagnosticLevel::Info => "Info".to_string(),
+                executor::DiagnosticLevel::Warning => "Warning".to_string(),
+                executor::DiagnosticLevel::Critical => "Critical".to_string(),
+            },
+            context: issue.context,
+            message: issue.message,
+        }
+    }
+}
+
+pub fn apply_module_filter(cli: &Cli, config: &mut Config) {
+    config.only_modules = cli.only.clone();
+
+    config.except_modules = cli.except.clone();
+
+    config.only_partitions = cli.mount_only_partitions.clone();
+
+    for id in inventory::unknown_filter_ids(config) {
+        eprintln!(
+            "Warning: module '{}' given to --only/--except does not exist",
+            id
+        );
+    }
+}
+
 fn load_config(cli: &Cli) -> Result<Config> {
+    let mut config = read_config(cli)?;
+
+    apply_module_filter(cli, &mut config);
+
+    Ok(config)
+}
+
+fn read_config(cli: &Cli) -> Result<Config> {
     if let Some(config_path) = &cli.config {
         return Config::from_file(config_path).with_context(|| {
             format!(
@@ -53,15 +166,25 @@ fn load_config(cli: &Cli) -> Result<Config> {
     }
 }
 
-pub fn handle_gen_config(output: &Path) -> Result<()> {
-    Config::default()
-        .save_to_file(output)
-        .with_context(|| format!("Failed to save generated config to {}", output.display()))
+pub fn handle_gen_config(output: &Path, minimal: bool) -> Result<()> {
+    let config = Config::default();
+
+    let result = if minimal {
+        config.save_to_file(output)
+    } else {
+        config.save_template_to_file(output)
+    };
+
+    result.with_context(|| format!("Failed to save generated config to {}", output.display()))
 }
 
 pub fn handle_show_config(cli: &Cli) -> Result<()> {
     let config = load_config(cli)?;
 
+    for warning in config.validate() {
+        eprintln!("Warning: {}", warning);
+    }
+
     let json = serde_json::to_string(&config).context("Failed to serialize config to JSON")?;
 
     println!("{}", json);
@@ -69,6 +192,44 @@ pub fn handle_show_config(cli: &Cli) -> Result<()> {
     Ok(())
 }
 
+pub fn handle_config_schema() -> Result<()> {
+    let schema = Config::schema().context("Failed to build config schema")?;
+
+    println!("{}", serde_json::to_string(&schema)?);
+
+    Ok(())
+}
+
+fn decode_hex_payload(payload: &str) -> Result<Vec<u8>> {
+    let payload = payload.trim();
+
+    if payload.len() % 2 != 0 {
+        bail!(
+            "Invalid hex payload: odd length {}, expected pairs of hex digits",
+            payload.len()
+        );
+    }
+
+    if let Some((pos, c)) = payload.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        bail!(
+            "Invalid hex payload: non-hex character {:?} at offset {}",
+            c,
+            pos
+        );
+    }
+
+    // All ASCII from here on, so every pair sits on a char boundary
+    payload
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).context("Invalid hex payload")?;
+
+            u8::from_str_radix(digits, 16).context("Invalid hex payload")
+        })
+        .collect()
+}
+
 pub fn handle_save_config(cli: &Cli, payload: &str) -> Result<()> {
     if let Ok(old_config) = load_config(cli)
         && let Err(e) = granary::create_silo(&old_config, "Auto-Backup", "Pre-WebUI Save")
@@ -76,15 +237,25 @@ pub fn handle_save_config(cli: &Cli, payload: &str) -> Result<()> {
         log::warn!("Failed to create Granary backup: {}", e);
     }
 
-    let json_bytes = (0..payload.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&payload[i..i + 2], 16))
-        .collect::<Result<Vec<u8>, _>>()
-        .context("Failed to decode hex payload")?;
+    let json_bytes = decode_hex_payload(payload)?;
 
     let config: Config =
         serde_json::from_slice(&json_bytes).context("Failed to parse config JSON payload")?;
 
+    let errors = config.validate_for_save();
+
+    if !errors.is_empty() {
+        let result = ActionResultJson {
+            action: "save-config",
+            success: false,
+            message: errors.join("; "),
+        };
+
+        println!("{}", serde_json::to_string(&result)?);
+
+        return Err(MountError::ConfigInvalid(errors).into());
+    }
+
     config
         .save_to_file(CONFIG_FILE_DEFAULT)
         .context("Failed to save config file")?;
@@ -94,28 +265,98 @@ pub fn handle_save_config(cli: &Cli, payload: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_save_rules(module: &str, payload: &str) -> Result<()> {
+pub fn handle_save_rules(module: &str, payload: &str, merge: bool) -> Result<()> {
     utils::validate_module_id(module).with_context(|| format!("Invalid module ID: {}", module))?;
 
-    let json_bytes = (0..payload.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&payload[i..i + 2], 16))
-        .collect::<Result<Vec<u8>, _>>()
-        .context("Failed to decode hex payload")?;
-
-    let _: inventory::ModuleRules =
-        serde_json::from_slice(&json_bytes).context("Invalid rules JSON")?;
+    let json_bytes = decode_hex_payload(payload)?;
 
-    let rules_dir = std::path::Path::new("/data/adb/meta-hybrid/rules");
+    let rules_dir = Path::new(defs::USER_RULES_DIR);
 
     std::fs::create_dir_all(rules_dir).context("Failed to create rules directory")?;
 
     let file_path = rules_dir.join(format!("{}.json", module));
 
-    std::fs::write(&file_path, json_bytes)
+    if !merge {
+        let _: inventory::ModuleRules =
+            serde_json::from_slice(&json_bytes).context("Invalid rules JSON")?;
+
+        utils::atomic_write(&file_path, json_bytes)
+            .with_context(|| format!("Failed to write rules file: {}", file_path.display()))?;
+
+        println!("Rules for module '{}' saved.", module);
+
+        return Ok(());
+    }
+
+    let patch: serde_json::Value =
+        serde_json::from_slice(&json_bytes).context("Invalid rules JSON")?;
+
+    // Held across read, merge and write so two panels saving at once apply both patches
+    let lock = fs::File::open(rules_dir).context("Failed to open rules directory")?;
+
+    rustix::fs::flock(&lock, rustix::fs::FlockOperation::LockExclusive)
+        .context("Failed to lock rules directory")?;
+
+    let mut rules = match fs::read_to_string(&file_path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Existing rules file is invalid: {}", file_path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({}),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read rules file: {}", file_path.display()));
+        }
+    };
+
+    merge_rules(&mut rules, patch)?;
+
+    let _: inventory::ModuleRules =
+        serde_json::from_value(rules.clone()).context("Merged rules are invalid")?;
+
+    let merged = serde_json::to_string_pretty(&rules)?;
+
+    utils::atomic_write(&file_path, merged)
         .with_context(|| format!("Failed to write rules file: {}", file_path.display()))?;
 
-    println!("Rules for module '{}' saved.", module);
+    println!("Rules for module '{}' merged.", module);
+
+    Ok(())
+}
+
+fn merge_rules(rules: &mut serde_json::Value, patch: serde_json::Value) -> Result<()> {
+    let serde_json::Value::Object(patch) = patch else {
+        bail!("Rules patch must be a JSON object");
+    };
+
+    let Some(rules) = rules.as_object_mut() else {
+        bail!("Existing rules file is not a JSON object");
+    };
+
+    for (key, value) in patch {
+        match (key.as_str(), value) {
+            ("default_mode", value) => {
+                rules.insert(key, value);
+            }
+            ("paths", serde_json::Value::Object(paths)) => {
+                let existing = rules
+                    .entry("paths")
+                    .or_insert_with(|| serde_json::json!({}));
+
+                let Some(existing) = existing.as_object_mut() else {
+                    bail!("Existing rules have a non-object paths entry");
+                };
+
+                for (path, mode) in paths {
+                    if mode.is_null() {
+                        existing.remove(&path);
+                    } else {
+                        existing.insert(path, mode);
+                    }
+                }
+            }
+            ("paths", _) => bail!("Rules patch 'paths' must be an object"),
+            (other, _) => bail!("Unknown rules field in patch: {}", other),
+        }
+    }
 
     Ok(())
 }
@@ -124,15 +365,212 @@ pub fn handle_storage() -> Result<()> {
     storage::print_status().context("Failed to retrieve storage status")
 }
 
+pub fn handle_status(cli: &Cli) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let status = StatusJson {
+        storage: storage::collect_status(),
+        modules: modules::list(&config).context("Failed to list modules")?,
+        silo_count: granary::list_silos().map(|s| s.len()).unwrap_or(0),
+        ratoon_count: granary::ratoon_count(),
+    };
+
+    println!("{}", serde_json::to_string(&status)?);
+
+    Ok(())
+}
+
 pub fn handle_modules(cli: &Cli) -> Result<()> {
     let config = load_config(cli)?;
 
     modules::print_list(&config).context("Failed to list modules")
 }
 
-pub fn handle_conflicts(cli: &Cli) -> Result<()> {
+pub fn handle_toggle(cli: &Cli, module: &str, enable: bool, preview: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    modules::set_enabled(&config, module, enable)
+        .with_context(|| format!("Failed to toggle module {}", module))?;
+
+    if !preview {
+        println!(
+            "Module '{}' {}.",
+            module,
+            if enable { "enabled" } else { "disabled" }
+        );
+
+        return Ok(());
+    }
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for plan preview")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan preview")?;
+
+    let preview = PlanPreviewJson {
+        overlay_modules: plan.overlay_module_ids,
+        magic_modules: plan.magic_module_ids,
+    };
+
+    println!("{}", serde_json::to_string(&preview)?);
+
+    Ok(())
+}
+
+// Every destructive command goes through here, so a shell invocation without --confirm
+// (boot scripts, WebUI buttons) can never change state by accident
+fn require_confirm(action: &str, confirm: bool, warning: &str) -> Result<()> {
+    if !confirm {
+        bail!("{}: {}. Pass --confirm to proceed.", action, warning);
+    }
+
+    Ok(())
+}
+
+// Destructive actions always answer with one JSON result, a refusal or failure included, so
+// the WebUI never has to parse free text. The error is still returned for the exit code
+fn run_action(action: &str, body: impl FnOnce() -> Result<String>) -> Result<()> {
+    let outcome = body();
+
+    let result = ActionResultJson {
+        action,
+        success: outcome.is_ok(),
+        message: match &outcome {
+            Ok(message) => message.clone(),
+            Err(e) => format!("{:#}", e),
+        },
+    };
+
+    println!("{}", serde_json::to_string(&result)?);
+
+    outcome.map(|_| ())
+}
+
+pub fn handle_disable_all(cli: &Cli, confirm: bool) -> Result<()> {
+    run_action("disable-all", || {
+        require_confirm("disable-all", confirm, "every module will be disabled")?;
+
+        let config = load_config(cli)?;
+
+        let count = granary::disable_all_modules(&config.moduledir)
+            .context("Failed to disable all modules")?;
+
+        Ok(format!("Disabled {} modules. Reboot to apply.", count))
+    })
+}
+
+pub fn handle_repair(cli: &Cli, confirm: bool) -> Result<()> {
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_DEFAULT));
+
+    let entries = granary::repair_files(&config_path, confirm).context("Failed to repair files")?;
+
+    if !confirm && !entries.is_empty() {
+        eprintln!(
+            "{} corrupt files found. Pass --confirm to back them up and rebuild them.",
+            entries.len()
+        );
+    }
+
+    println!("{}", serde_json::to_string(&entries)?);
+
+    Ok(())
+}
+
+pub fn handle_clean(cli: &Cli, keep_silos: bool, confirm: bool) -> Result<()> {
+    run_action("clean", || {
+        require_confirm(
+            "clean",
+            confirm,
+            "module storage, runtime state and the Ratoon counter will be wiped",
+        )?;
+
+        // Held while cleaning so a daemon cannot start mounting from the storage being wiped
+        let Some(_daemon_lock) = utils::acquire_daemon_lock()? else {
+            bail!("Daemon is still running, refusing to clean");
+        };
+
+        let config = load_config(cli)?;
+
+        let actions = granary::clean(&config, keep_silos).context("Failed to clean")?;
+
+        Ok(if actions.is_empty() {
+            "Nothing to clean.".to_string()
+        } else {
+            format!(
+                "Cleaned: {}. Reboot to rebuild storage.",
+                actions.join(", ")
+            )
+        })
+    })
+}
+
+pub fn handle_nuke(cli: &Cli, target: &str, confirm: bool) -> Result<()> {
+    run_action("nuke", || {
+        require_confirm(
+            "nuke",
+            confirm,
+            "the ext4 sysfs entry is removed until reboot and may destabilize KSU hiding",
+        )?;
+
+        if !Path::new(target).is_absolute() {
+            bail!("Nuke target must be an absolute path: {}", target);
+        }
+
+        let config = load_config(cli)?;
+
+        // The driver probe depends on the configured root backend
+        try_umount::init(&config);
+
+        if !try_umount::is_driver_available() {
+            return Err(MountError::KsuDriverMissing)
+                .with_context(|| format!("Refusing to nuke {}", target));
+        }
+
+        try_umount::ksu_nuke_sysfs(target)
+            .with_context(|| format!("Failed to nuke sysfs for {}", target))?;
+
+        Ok(format!("Nuked ext4 sysfs entry for {}.", target))
+    })
+}
+
+pub fn handle_enable_all(cli: &Cli, confirm: bool) -> Result<()> {
+    run_action("enable-all", || {
+        require_confirm(
+            "enable-all",
+            confirm,
+            "re-enabling every module may re-trigger a bootloop",
+        )?;
+
+        let config = load_config(cli)?;
+
+        let count = granary::enable_all_modules(&config.moduledir)
+            .context("Failed to enable all modules")?;
+
+        Ok(format!("Enabled {} modules. Reboot to apply.", count))
+    })
+}
+
+pub fn handle_check_updates(cli: &Cli) -> Result<()> {
     let config = load_config(cli)?;
 
+    modules::check_updates(&config).context("Failed to check module updates")
+}
+
+pub fn handle_conflicts(cli: &Cli, simulate_rules: &[String]) -> Result<()> {
+    let mut config = load_config(cli)?;
+
+    for rule in simulate_rules {
+        let (path, id) = rule.split_once(':').with_context(|| {
+            format!("Invalid simulated rule '{}', expected path:module_id", rule)
+        })?;
+
+        config.winnowing.set_rule(path, id);
+    }
+
     let module_list = inventory::scan(&config.moduledir, &config)
         .context("Failed to scan modules for conflict analysis")?;
 
@@ -153,26 +591,18 @@ pub fn handle_conflicts(cli: &Cli) -> Result<()> {
 pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
     let config = load_config(cli)?;
 
-    let module_list = inventory::scan(&config.moduledir, &config)
+    let scan = inventory::scan_report(&config.moduledir, &config)
         .context("Failed to scan modules for diagnostics")?;
 
-    let plan = planner::generate(&config, &module_list, &config.moduledir)
+    let plan = planner::generate(&config, &scan.modules, &config.moduledir)
         .context("Failed to generate plan for diagnostics")?;
 
-    let issues = executor::diagnose_plan(&plan);
+    let mut issues = executor::diagnose_plan(&plan, &config);
 
-    let json_issues: Vec<DiagnosticIssueJson> = issues
-        .into_iter()
-        .map(|i| DiagnosticIssueJson {
-            level: match i.level {
-                executor::DiagnosticLevel::Info => "Info".to_string(),
-                executor::DiagnosticLevel::Warning => "Warning".to_string(),
-                executor::DiagnosticLevel::Critical => "Critical".to_string(),
-            },
-            context: i.context,
-            message: i.message,
-        })
-        .collect();
+    issues.extend(scan.issues.into_iter().map(executor::DiagnosticIssue::from));
+
+    let json_issues: Vec<DiagnosticIssueJson> =
+        issues.into_iter().map(DiagnosticIssueJson::from).collect();
 
     let json =
         serde_json::to_string(&json_issues).context("Failed to serialize diagnostics report")?;
@@ -182,7 +612,377 @@ pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Result<()> {
+fn watch_report(config: &Config) -> Result<WatchReportJson> {
+    let scan = inventory::scan_report(&config.moduledir, config)?;
+
+    let plan = planner::generate(config, &scan.modules, &config.moduledir)?;
+
+    let conflicts = winnow::sift_conflicts(plan.analyze_conflicts().details, &config.winnowing);
+
+    let mut issues = executor::diagnose_plan(&plan, config);
+
+    issues.extend(scan.issues.into_iter().map(executor::DiagnosticIssue::from));
+
+    Ok(WatchReportJson {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        modules: scan.modules.len(),
+        conflicts,
+        diagnostics: issues.into_iter().map(DiagnosticIssueJson::from).collect(),
+    })
+}
+
+// Only ever plans against the module dir, one JSON report per line on every settled change
+pub fn handle_watch(cli: &Cli, debounce_ms: u64) -> Result<()> {
+    let mut config = load_config(cli)?;
+
+    config.dry_run = true;
+
+    let print_report = |config: &Config| match watch_report(config) {
+        Ok(report) => match serde_json::to_string(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Failed to serialize watch report: {}", e),
+        },
+        Err(e) => eprintln!("Re-plan failed: {:#}", e),
+    };
+
+    print_report(&config);
+
+    eprintln!(
+        "Watching {} for changes, Ctrl-C to stop.",
+        config.moduledir.display()
+    );
+
+    watch::run(
+        &config.moduledir,
+        Duration::from_millis(debounce_ms),
+        || print_report(&config),
+    )
+}
+
+pub fn handle_export_mounts(cli: &Cli) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for mount export")?;
+
+    // Point layers at the synced storage when it is mounted, like the daemon does
+    let mnt_base = Path::new(&config.hybrid_mnt_dir);
+
+    let storage_root = if utils::is_mounted(mnt_base) {
+        mnt_base
+    } else {
+        config.moduledir.as_path()
+    };
+
+    let plan = planner::generate(&config, &module_list, storage_root)
+        .context("Failed to generate plan for mount export")?;
+
+    let script =
+        executor::render_script(&plan, &config).context("Failed to render mount script")?;
+
+    print!("{}", script);
+
+    Ok(())
+}
+
+pub fn handle_tree(cli: &Cli, modules: &[String]) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for tree export")?;
+
+    for id in modules {
+        if !module_list.iter().any(|m| &m.id == id) {
+            bail!("Module {} is not enabled or does not exist", id);
+        }
+    }
+
+    let module_paths: Vec<PathBuf> = module_list
+        .into_iter()
+        .filter(|m| modules.is_empty() || modules.contains(&m.id))
+        .map(|m| m.source_path)
+        .collect();
+
+    let tree = magic::collect_module_files(
+        &module_paths,
+        &config.partitions,
+        &HashMap::new(),
+        &config.partition_symlink_policy,
+    )
+    .context("Failed to build magic mount tree")?;
+
+    let json = serde_json::to_string(&tree).context("Failed to serialize mount tree")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+fn timed<T>(phases: &mut Vec<BenchmarkPhaseJson>, phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+
+    let result = f();
+
+    phases.push(BenchmarkPhaseJson {
+        phase,
+        millis: start.elapsed().as_secs_f64() * 1000.0,
+    });
+
+    result
+}
+
+pub fn handle_benchmark(cli: &Cli) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let mut phases = Vec::new();
+
+    let module_list = timed(&mut phases, "scan", || {
+        inventory::scan(&config.moduledir, &config)
+    })
+    .context("Failed to scan modules for benchmark")?;
+
+    let plan = timed(&mut phases, "plan", || {
+        planner::generate(&config, &module_list, &config.moduledir)
+    })
+    .context("Failed to generate plan for benchmark")?;
+
+    timed(&mut phases, "conflicts", || plan.analyze_conflicts());
+
+    let all_paths: Vec<PathBuf> = module_list.iter().map(|m| m.source_path.clone()).collect();
+
+    timed(&mut phases, "magic_tree_all", || {
+        magic::collect_module_files(
+            &all_paths,
+            &config.partitions,
+            &HashMap::new(),
+            &config.partition_symlink_policy,
+        )
+    })
+    .context("Failed to build magic mount tree for benchmark")?;
+
+    // Rendering walks every mount decision without touching the real system tree
+    timed(&mut phases, "mount_setup_simulated", || {
+        executor::render_script(&plan, &config)
+    })
+    .context("Failed to simulate mount setup for benchmark")?;
+
+    let report = BenchmarkJson {
+        modules: module_list.len(),
+        overlay_modules: plan.overlay_module_ids.len(),
+        magic_modules: plan.magic_module_ids.len(),
+        phases,
+    };
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
+
+pub fn handle_diff(cli: &Cli, snapshot: &str, save: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for plan diff")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan for diff")?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let current = plan.snapshot(snapshot, now);
+
+    if save {
+        granary::save_plan_snapshot(&current)
+            .with_context(|| format!("Failed to save plan snapshot {}", snapshot))?;
+
+        println!("Plan snapshot '{}' saved.", snapshot);
+
+        return Ok(());
+    }
+
+    let baseline = granary::load_plan_snapshot(snapshot)?;
+
+    let diff = baseline.diff(&current);
+
+    let json = serde_json::to_string(&diff).context("Failed to serialize plan diff")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+pub fn handle_log(lines: usize, json: bool) -> Result<()> {
+    let log_lines = utils::read_log_tail(Path::new(defs::DAEMON_LOG_FILE), lines)
+        .context("Failed to read daemon log")?;
+
+    if json {
+        println!("{}", serde_json::to_string(&log_lines)?);
+    } else {
+        for line in log_lines {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn handle_prop(cli: &Cli, module: &str, key: &str, value: Option<&str>) -> Result<()> {
+    let config = load_config(cli)?;
+
+    modules::prop(&config, module, key, value)
+        .with_context(|| format!("Failed to access module.prop of {}", module))
+}
+
+pub fn handle_export(cli: &Cli, output: &Path) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let mut rules = BTreeMap::new();
+
+    if let Ok(entries) = fs::read_dir(defs::USER_RULES_DIR) {
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            let Some(id) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                continue;
+            };
+
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read rules file: {}", path.display()))?;
+
+            let module_rules: inventory::ModuleRules = serde_json::from_str(&content)
+                .with_context(|| format!("Invalid rules file: {}", path.display()))?;
+
+            rules.insert(id, module_rules);
+        }
+    }
+
+    let bundle = ConfigBundle {
+        version: CONFIG_BUNDLE_VERSION,
+        exported_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        config,
+        rules,
+    };
+
+    let json = serde_json::to_string_pretty(&bundle).context("Failed to serialize bundle")?;
+
+    utils::atomic_write(output, json)
+        .with_context(|| format!("Failed to write bundle to {}", output.display()))?;
+
+    println!(
+        "Exported config and {} module rules to {}.",
+        bundle.rules.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+pub fn handle_import(cli: &Cli, input: &Path) -> Result<()> {
+    let content = fs::read_to_string(input)
+        .with_context(|| format!("Failed to read bundle {}", input.display()))?;
+
+    let bundle: ConfigBundle =
+        serde_json::from_str(&content).context("Failed to parse config bundle")?;
+
+    if bundle.version > CONFIG_BUNDLE_VERSION {
+        bail!(
+            "Bundle version {} is newer than supported version {}",
+            bundle.version,
+            CONFIG_BUNDLE_VERSION
+        );
+    }
+
+    for id in bundle.rules.keys() {
+        utils::validate_module_id(id)
+            .with_context(|| format!("Invalid module ID in bundle: {}", id))?;
+    }
+
+    for warning in bundle.config.validate() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if let Ok(old_config) = load_config(cli)
+        && let Err(e) = granary::create_silo(&old_config, "Auto-Backup", "Pre-Import")
+    {
+        log::warn!("Failed to create Granary backup: {}", e);
+    }
+
+    bundle
+        .config
+        .save_to_file(CONFIG_FILE_DEFAULT)
+        .context("Failed to save config file")?;
+
+    let rules_dir = Path::new(defs::USER_RULES_DIR);
+
+    fs::create_dir_all(rules_dir).context("Failed to create rules directory")?;
+
+    for (id, module_rules) in &bundle.rules {
+        let file_path = rules_dir.join(format!("{}.json", id));
+
+        let json = serde_json::to_string(module_rules)?;
+
+        utils::atomic_write(&file_path, json)
+            .with_context(|| format!("Failed to write rules file: {}", file_path.display()))?;
+    }
+
+    println!(
+        "Imported config and {} module rules from {}.",
+        bundle.rules.len(),
+        input.display()
+    );
+
+    Ok(())
+}
+
+fn list_winnow_rules(config: &Config) -> Result<Vec<WinnowRuleJson>> {
+    let module_list = inventory::scan(&config.moduledir, config)
+        .context("Failed to scan modules for winnowing rules")?;
+
+    let plan = planner::generate(config, &module_list, &config.moduledir)
+        .context("Failed to generate plan for winnowing rules")?;
+
+    // A rule is live only while its path is contested and its module is one of the contenders
+    let contested: HashMap<String, Vec<String>> = plan
+        .analyze_conflicts()
+        .details
+        .into_iter()
+        .map(|c| {
+            (
+                format!("/{}/{}", c.partition, c.relative_path),
+                c.contending_modules,
+            )
+        })
+        .collect();
+
+    let mut rules: Vec<WinnowRuleJson> = config
+        .winnowing
+        .rules
+        .iter()
+        .map(|(path, id)| WinnowRuleJson {
+            path: path.clone(),
+            module_id: id.clone(),
+            matched: contested
+                .get(path)
+                .is_some_and(|contenders| contenders.contains(id)),
+        })
+        .collect();
+
+    rules.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(rules)
+}
+
+pub fn handle_system_action(
+    cli: &Cli,
+    action: &str,
+    value: Option<&str>,
+    confirm: bool,
+) -> Result<()> {
     let mut config = load_config(cli)?;
 
     match action {
@@ -200,24 +1000,55 @@ pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Res
 
             println!("Silo created.");
         }
-        "granary-delete" => {
-            if let Some(id) = value {
-                granary::delete_silo(id)?;
-
-                println!("Silo {} deleted.", id);
-            } else {
+        "granary-delete" => run_action(action, || {
+            let Some(id) = value else {
                 bail!("Missing Silo ID");
-            }
-        }
+            };
+
+            require_confirm(action, confirm, "the silo will be deleted permanently")?;
+
+            granary::delete_silo(id)?;
+
+            Ok(format!("Silo {} deleted.", id))
+        })?,
         "granary-restore" => {
-            if let Some(id) = value {
-                granary::restore_silo(id)?;
+            if let Some(id) = value.and_then(|v| v.strip_suffix(":preview")) {
+                let diff = granary::diff_silo(id, &config)?;
+
+                let json = serde_json::to_string(&diff)?;
 
-                println!("Silo {} restored. Please reboot.", id);
+                println!("{}", json);
             } else {
-                bail!("Missing Silo ID");
+                run_action(action, || {
+                    let Some(id) = value else {
+                        bail!("Missing Silo ID");
+                    };
+
+                    require_confirm(
+                        action,
+                        confirm,
+                        "the current config and state will be replaced",
+                    )?;
+
+                    granary::restore_silo(id)?;
+
+                    Ok(format!("Silo {} restored. Please reboot.", id))
+                })?;
             }
         }
+        "granary-ratoon" => {
+            let ratoon = RatoonJson {
+                count: granary::ratoon_count(),
+                threshold: granary::RATOON_THRESHOLD,
+            };
+
+            println!("{}", serde_json::to_string(&ratoon)?);
+        }
+        "granary-reset-counter" => run_action(action, || {
+            granary::reset_ratoon_counter()?;
+
+            Ok("Ratoon counter reset.".to_string())
+        })?,
         "winnow-set" => {
             if let Some(val) = value
                 && let Some((path, id)) = val.split_once(':')
@@ -229,6 +1060,24 @@ pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Res
                 println!("Winnowing rule set: {} -> {}", path, id);
             }
         }
+        "winnow-list" => {
+            let rules = list_winnow_rules(&config)?;
+
+            println!("{}", serde_json::to_string(&rules)?);
+        }
+        "winnow-unset" => run_action(action, || {
+            let Some(path) = value else {
+                bail!("Missing winnowing rule path");
+            };
+
+            if config.winnowing.unset_rule(path).is_none() {
+                bail!("No winnowing rule for {}", path);
+            }
+
+            config.save_to_file(CONFIG_FILE_DEFAULT)?;
+
+            Ok(format!("Winnowing rule removed: {}", path))
+        })?,
         _ => bail!("Unknown action: {}", action),
     }
 
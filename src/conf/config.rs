@@ -8,22 +8,114 @@ use std::{
 };
 
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, MapAccess, Visitor},
+};
 
 pub const CONFIG_FILE_DEFAULT: &str = "/data/adb/meta-hybrid/config.toml";
-use crate::defs::DEFAULT_HYBRID_MNT_DIR;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct WinnowingTable {
-    #[serde(flatten)]
+// Top-level dirs that are never a partition, mounting a module tree over them breaks the boot
+const RESERVED_PARTITIONS: [&str; 8] = [
+    "data",
+    "dev",
+    "proc",
+    "sys",
+    "mnt",
+    "storage",
+    "metadata",
+    "debug_ramdisk",
+];
+
+const MAX_EXTRA_PARTITIONS: usize = 32;
+
+const RESERVED_OVERLAY_OPTIONS: [&str; 4] = ["lowerdir", "upperdir", "workdir", "source"];
+
+use crate::{
+    conf::cli::Cli,
+    core::inventory::MountMode,
+    defs::{BASE_DIR, BUILTIN_PARTITIONS, DEFAULT_HYBRID_MNT_DIR},
+    error::MountError,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WinnowingStrategy {
+    // The first contender sits in the top overlay layer, so it is what the mount shows
+    #[default]
+    #[serde(alias = "highest")]
+    First,
+    Last,
+    Alphabetical,
+}
+
+impl WinnowingStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WinnowingStrategy::First => "first",
+            WinnowingStrategy::Last => "last",
+            WinnowingStrategy::Alphabetical => "alphabetical",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct WinnowingConfig {
+    pub strategy: WinnowingStrategy,
     pub rules: HashMap<String, String>,
 }
 
-impl WinnowingTable {
-    pub fn get_preferred_module(&self, file_path: &Path) -> Option<String> {
-        let path_str = file_path.to_string_lossy().to_string();
+struct WinnowingVisitor;
+
+impl<'de> Visitor<'de> for WinnowingVisitor {
+    type Value = WinnowingConfig;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a winnowing table")
+    }
+
+    // Keys are told apart by shape rather than by trying whole layouts, so a typo in a typed
+    // field is reported as such instead of turning into a bogus legacy rule
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut config = WinnowingConfig::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "strategy" | "default_strategy" => {
+                    config.strategy = map
+                        .next_value()
+                        .map_err(|e| de::Error::custom(format!("winnowing.{key}: {e}")))?;
+                }
+                "rules" => config
+                    .rules
+                    .extend(map.next_value::<HashMap<String, String>>()?),
+                // Older configs kept the rules inline as `[winnowing] "/path" = "module"`
+                _ if key.starts_with('/') => {
+                    let module = map.next_value()?;
+                    config.rules.insert(key, module);
+                }
+                _ => {
+                    return Err(de::Error::custom(format!(
+                        "unknown winnowing key `{key}`, expected `strategy`, `rules` or an \
+                         absolute path"
+                    )));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl<'de> Deserialize<'de> for WinnowingConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(WinnowingVisitor)
+    }
+}
 
-        self.rules.get(&path_str).cloned()
+impl WinnowingConfig {
+    pub fn get(&self, file_path: &Path) -> Option<&String> {
+        self.rules.get(file_path.to_string_lossy().as_ref())
     }
 
     pub fn set_rule(&mut self, file_path: &str, module_id: &str) {
@@ -31,9 +123,8 @@ impl WinnowingTable {
             .insert(file_path.to_string(), module_id.to_string());
     }
 
-    #[allow(dead_code)]
-    pub fn remove_rule(&mut self, file_path: &str) {
-        self.rules.remove(file_path);
+    pub fn unset_rule(&mut self, file_path: &str) -> Option<String> {
+        self.rules.remove(file_path)
     }
 }
 
@@ -62,13 +153,76 @@ impl Default for GranaryConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MountRetryConfig {
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    #[serde(default = "default_retry_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    50
+}
+
+impl Default for MountRetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: default_retry_attempts(),
+            backoff_ms: default_retry_backoff_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, clap::ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum OverlayMode {
     #[default]
     Tmpfs,
     Ext4,
     Erofs,
+    None,
+    Auto,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifySync {
+    #[default]
+    Off,
+    Size,
+    Content,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RootBackend {
+    #[default]
+    Ksu,
+    Apatch,
+    Magisk,
+}
+
+impl RootBackend {
+    pub fn mount_source(&self) -> &'static str {
+        match self {
+            Self::Ksu => "KSU",
+            Self::Apatch => "APatch",
+            Self::Magisk => "magisk",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -91,17 +245,231 @@ pub struct Config {
     #[serde(default)]
     pub dry_run: bool,
     #[serde(default)]
-    pub winnowing: WinnowingTable,
+    pub winnowing: WinnowingConfig,
     #[serde(default)]
     pub granary: GranaryConfig,
     #[serde(default = "default_hybrid_mnt_dir")]
     pub hybrid_mnt_dir: String,
+    #[serde(default)]
+    pub partition_symlink_policy: HashMap<String, bool>,
+    #[serde(default)]
+    pub partition_modes: HashMap<String, MountMode>,
+    #[serde(default)]
+    pub cleanup_orphan_mounts: bool,
+    #[serde(default = "default_ignored_module_ids")]
+    pub ignored_module_ids: Vec<String>,
+    #[serde(default)]
+    pub tempdir: Option<PathBuf>,
+    #[serde(default)]
+    pub root_backend: RootBackend,
+    #[serde(default = "default_max_log_size_kb")]
+    pub max_log_size_kb: u64,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default = "default_preserve_sync_metadata")]
+    pub preserve_sync_metadata: bool,
+    #[serde(default)]
+    pub overlay_userxattr: Option<bool>,
+    #[serde(default)]
+    pub overlay_options: Vec<String>,
+    #[serde(default)]
+    pub overlay_writable: bool,
+    #[serde(default)]
+    pub use_private_namespace: bool,
+    #[serde(default)]
+    pub mount_retries: MountRetryConfig,
+    #[serde(default)]
+    pub verify_sync: VerifySync,
+    #[serde(default)]
+    pub auto_partitions: bool,
+    #[serde(default = "default_mount_timeout_secs")]
+    pub mount_timeout_secs: u64,
+    #[serde(default = "default_camouflage")]
+    pub camouflage: bool,
+    #[serde(default)]
+    pub image_path: Option<PathBuf>,
+    #[serde(default)]
+    pub fsck_every_boot: bool,
+    #[serde(default)]
+    pub tmpfs_size_mb: u64,
+    #[serde(default = "default_hook_timeout_secs")]
+    pub hook_timeout_secs: u64,
+    #[serde(default)]
+    pub scan_threads: usize,
+    #[serde(skip)]
+    pub only_modules: Vec<String>,
+    #[serde(skip)]
+    pub except_modules: Vec<String>,
+    #[serde(skip)]
+    pub only_partitions: Vec<String>,
+}
+
+// One settings entry for generated forms, nested tables are flattened to dotted keys
+#[derive(Debug, Serialize)]
+pub struct ConfigField {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub default: serde_json::Value,
+    pub help: String,
+}
+
+fn json_kind(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(n) if n.is_f64() => "number",
+        serde_json::Value::Number(_) => "integer",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "table",
+    }
+}
+
+// Help text is the comment block right above a key, header or commented example in the
+// template, so documenting a field there is all it takes to describe it in the schema
+fn template_help(template: &str) -> HashMap<String, (String, Option<&'static str>)> {
+    let mut help = HashMap::new();
+
+    let mut section = String::new();
+
+    let mut comment: Vec<&str> = Vec::new();
+
+    for line in template.lines().map(str::trim) {
+        if line.is_empty() {
+            comment.clear();
+
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = header.to_string();
+
+            help.insert(section.clone(), (comment.join(" "), None));
+
+            comment.clear();
+
+            continue;
+        }
+
+        let assignment = line.trim_start_matches('#').trim_start();
+
+        let key = assignment
+            .split_once('=')
+            .map(|(k, _)| k.trim())
+            .filter(|k| !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+
+        match key {
+            Some(key) => {
+                let full_key = if section.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", section, key)
+                };
+
+                // A commented example still tells the type of a field that defaults to unset
+                let kind = assignment
+                    .parse::<toml::Table>()
+                    .ok()
+                    .and_then(|t| t.get(key).map(|v| v.type_str()))
+                    .map(|k| match k {
+                        "float" => "number",
+                        other => other,
+                    });
+
+                help.entry(full_key)
+                    .or_insert_with(|| (comment.join(" "), kind));
+            }
+            None => comment.push(line.trim_start_matches('#').trim()),
+        }
+    }
+
+    help
+}
+
+fn collect_fields(
+    prefix: &str,
+    value: &serde_json::Value,
+    help: &HashMap<String, (String, Option<&'static str>)>,
+    fields: &mut Vec<ConfigField>,
+) {
+    let Some(object) = value.as_object() else {
+        return;
+    };
+
+    for (name, value) in object {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        // Structs serialize to populated objects, maps keyed by user data start out empty
+        if value.as_object().is_some_and(|o| !o.is_empty()) {
+            collect_fields(&key, value, help, fields);
+
+            continue;
+        }
+
+        let (mut text, example_kind) = help.get(&key).cloned().unwrap_or_default();
+
+        // Fields of a table share the comment above its header
+        if text.is_empty()
+            && let Some((section_text, _)) = help.get(prefix)
+        {
+            text = section_text.clone();
+        }
+
+        let kind = match json_kind(value) {
+            "null" => example_kind.unwrap_or("null"),
+            kind => kind,
+        };
+
+        fields.push(ConfigField {
+            key,
+            kind,
+            default: value.clone(),
+            help: text,
+        });
+    }
+}
+
+fn toml_value<T: Serialize>(value: &T) -> String {
+    toml::Value::try_from(value)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "\"\"".to_string())
 }
 
 fn default_hybrid_mnt_dir() -> String {
     DEFAULT_HYBRID_MNT_DIR.to_string()
 }
 
+fn default_ignored_module_ids() -> Vec<String> {
+    ["meta-hybrid", "lost+found", ".git", ".idea", ".vscode"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_hook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_log_size_kb() -> u64 {
+    1024
+}
+
+fn default_preserve_sync_metadata() -> bool {
+    true
+}
+
+fn default_mount_timeout_secs() -> u64 {
+    120
+}
+
+fn default_camouflage() -> bool {
+    true
+}
+
 fn default_moduledir() -> PathBuf {
     PathBuf::from("/data/adb/modules/")
 }
@@ -143,9 +511,35 @@ impl Default for Config {
             disable_umount: false,
             allow_umount_coexistence: false,
             dry_run: false,
-            winnowing: WinnowingTable::default(),
+            winnowing: WinnowingConfig::default(),
             granary: GranaryConfig::default(),
             hybrid_mnt_dir: default_hybrid_mnt_dir(),
+            partition_symlink_policy: HashMap::new(),
+            partition_modes: HashMap::new(),
+            cleanup_orphan_mounts: false,
+            ignored_module_ids: default_ignored_module_ids(),
+            tempdir: None,
+            root_backend: RootBackend::default(),
+            max_log_size_kb: default_max_log_size_kb(),
+            log_format: LogFormat::default(),
+            preserve_sync_metadata: default_preserve_sync_metadata(),
+            overlay_userxattr: None,
+            overlay_options: Vec::new(),
+            overlay_writable: false,
+            use_private_namespace: false,
+            mount_retries: MountRetryConfig::default(),
+            verify_sync: VerifySync::default(),
+            auto_partitions: false,
+            mount_timeout_secs: default_mount_timeout_secs(),
+            camouflage: default_camouflage(),
+            image_path: None,
+            fsck_every_boot: false,
+            tmpfs_size_mb: 0,
+            hook_timeout_secs: default_hook_timeout_secs(),
+            scan_threads: 0,
+            only_modules: Vec::new(),
+            except_modules: Vec::new(),
+            only_partitions: Vec::new(),
         }
     }
 }
@@ -154,11 +548,231 @@ impl Config {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = fs::read_to_string(path.as_ref()).context("failed to read config file")?;
 
-        let config: Config = toml::from_str(&content).context("failed to parse config file")?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| MountError::ConfigInvalid(vec![e.to_string()]))
+            .context("failed to parse config file")?;
 
         Ok(config)
     }
 
+    pub fn apply_root_backend(&mut self) {
+        if self.mountsource == default_mountsource() {
+            self.mountsource = self.root_backend.mount_source().to_string();
+        }
+    }
+
+    // ZygiskSU enforce unmounts for denylisted apps itself, so try-umount steps aside unless
+    // the user explicitly allowed both to run
+    pub fn umount_disabled(&self, zygisksu_enforce: bool) -> bool {
+        self.disable_umount || (zygisksu_enforce && !self.allow_umount_coexistence)
+    }
+
+    // Layers and the source are owned by the planner, and a comma would smuggle extra options
+    // into the legacy mount data string
+    fn overlay_option_problem(option: &str) -> Option<&'static str> {
+        let key = option.split_once('=').map_or(option, |(key, _)| key);
+
+        if key.is_empty() || option.contains([',', ' ', '\t', '\n']) {
+            Some("is not a single key or key=value option")
+        } else if RESERVED_OVERLAY_OPTIONS.contains(&key) {
+            Some("is managed by meta-hybrid and cannot be overridden")
+        } else {
+            None
+        }
+    }
+
+    pub fn effective_overlay_options(&self) -> Vec<String> {
+        self.overlay_options
+            .iter()
+            .filter(|option| Self::overlay_option_problem(option).is_none())
+            .cloned()
+            .collect()
+    }
+
+    fn is_valid_image_path(path: &Path) -> bool {
+        path.is_absolute() && path.starts_with("/data")
+    }
+
+    pub fn image_path(&self) -> PathBuf {
+        match &self.image_path {
+            Some(path) if Self::is_valid_image_path(path) => path.clone(),
+            _ => Path::new(BASE_DIR).join("modules.img"),
+        }
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.enable_nuke && !matches!(self.overlay_mode, OverlayMode::Ext4 | OverlayMode::Auto) {
+            warnings.push(
+                "enable_nuke only applies to the ext4 storage backend and will be ignored"
+                    .to_string(),
+            );
+        }
+
+        if self.disable_umount && self.allow_umount_coexistence {
+            warnings.push(
+                "allow_umount_coexistence has no effect while disable_umount is set".to_string(),
+            );
+        }
+
+        if let Some(path) = &self.image_path
+            && !Self::is_valid_image_path(path)
+        {
+            warnings.push(format!(
+                "image_path {} must be an absolute path under /data, using the default image",
+                path.display()
+            ));
+        }
+
+        if !Path::new(&self.hybrid_mnt_dir).is_absolute() {
+            warnings.push(format!(
+                "hybrid_mnt_dir '{}' is not an absolute path",
+                self.hybrid_mnt_dir
+            ));
+        }
+
+        if self.use_private_namespace {
+            warnings.push(
+                "use_private_namespace is for testing only, its mounts vanish when the daemon \
+                 exits and the system boots without modules"
+                    .to_string(),
+            );
+        }
+
+        for option in &self.overlay_options {
+            if let Some(problem) = Self::overlay_option_problem(option) {
+                warnings.push(format!(
+                    "overlay option '{}' {} and will be ignored",
+                    option, problem
+                ));
+            }
+        }
+
+        for partition in &self.partitions {
+            if BUILTIN_PARTITIONS.contains(&partition.as_str()) {
+                warnings.push(format!(
+                    "partition '{}' is built-in, listing it in partitions is redundant",
+                    partition
+                ));
+            } else if !Path::new("/").join(partition).is_dir() {
+                warnings.push(format!(
+                    "partition '{}' does not exist at /{} and will be ignored",
+                    partition, partition
+                ));
+            }
+        }
+
+        for partition in self.partition_modes.keys() {
+            if !BUILTIN_PARTITIONS.contains(&partition.as_str())
+                && !self.partitions.contains(partition)
+            {
+                warnings.push(format!(
+                    "partition_modes entry '{}' is not a known partition and will be ignored",
+                    partition
+                ));
+            }
+        }
+
+        let mut dangling: Vec<(&String, &String)> = self
+            .winnowing
+            .rules
+            .iter()
+            .filter(|(_, id)| !self.moduledir.join(id).is_dir())
+            .collect();
+
+        dangling.sort();
+
+        for (path, id) in dangling {
+            warnings.push(format!(
+                "winnowing rule for {} points to module '{}', which is not installed",
+                path, id
+            ));
+        }
+
+        warnings
+    }
+
+    // Unlike validate, these are problems that would break the next boot, so a config that
+    // has any of them is refused instead of saved
+    pub fn validate_for_save(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if !self.moduledir.is_absolute() {
+            errors.push(format!(
+                "moduledir '{}' is not an absolute path",
+                self.moduledir.display()
+            ));
+        } else if !self.moduledir.is_dir() {
+            errors.push(format!(
+                "moduledir '{}' does not exist",
+                self.moduledir.display()
+            ));
+        }
+
+        if let Some(tempdir) = &self.tempdir {
+            if !tempdir.is_absolute() {
+                errors.push(format!(
+                    "tempdir '{}' is not an absolute path",
+                    tempdir.display()
+                ));
+            } else if !tempdir.is_dir() {
+                errors.push(format!("tempdir '{}' does not exist", tempdir.display()));
+            }
+        }
+
+        if !Path::new(&self.hybrid_mnt_dir).is_absolute() {
+            errors.push(format!(
+                "hybrid_mnt_dir '{}' is not an absolute path",
+                self.hybrid_mnt_dir
+            ));
+        }
+
+        if let Some(path) = &self.image_path
+            && !Self::is_valid_image_path(path)
+        {
+            errors.push(format!(
+                "image_path '{}' must be an absolute path under /data",
+                path.display()
+            ));
+        }
+
+        for option in &self.overlay_options {
+            if let Some(problem) = Self::overlay_option_problem(option) {
+                errors.push(format!("overlay option '{}' {}", option, problem));
+            }
+        }
+
+        if self.partitions.len() > MAX_EXTRA_PARTITIONS {
+            errors.push(format!(
+                "{} extra partitions listed, at most {} are allowed",
+                self.partitions.len(),
+                MAX_EXTRA_PARTITIONS
+            ));
+        }
+
+        for partition in &self.partitions {
+            let plain_name = !partition.is_empty()
+                && partition != "."
+                && partition != ".."
+                && !partition.contains('/');
+
+            if !plain_name {
+                errors.push(format!(
+                    "partition '{}' must be a single directory name under /",
+                    partition
+                ));
+            } else if RESERVED_PARTITIONS.contains(&partition.as_str()) {
+                errors.push(format!(
+                    "partition '{}' is a reserved system directory and cannot be mounted over",
+                    partition
+                ));
+            }
+        }
+
+        errors
+    }
+
     pub fn load_default() -> Result<Self> {
         Self::from_file(CONFIG_FILE_DEFAULT)
     }
@@ -166,41 +780,271 @@ impl Config {
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let content = toml::to_string_pretty(self).context("failed to serialize config")?;
 
-        if let Some(parent) = path.as_ref().parent() {
+        Self::write_config_file(path.as_ref(), &content)
+    }
+
+    pub fn save_template_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Self::write_config_file(path.as_ref(), &self.to_commented_toml())
+    }
+
+    fn write_config_file(path: &Path, content: &str) -> Result<()> {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).context("failed to create config directory")?;
         }
 
-        fs::write(path.as_ref(), content).context("failed to write config file")?;
+        fs::write(path, content).context("failed to write config file")?;
 
         Ok(())
     }
 
-    pub fn merge_with_cli(
-        &mut self,
-        moduledir: Option<PathBuf>,
-        mountsource: Option<String>,
-        verbose: bool,
-        partitions: Vec<String>,
-        dry_run: bool,
-    ) {
-        if let Some(dir) = moduledir {
-            self.moduledir = dir;
+    pub fn to_commented_toml(&self) -> String {
+        format!(
+            r#"# Meta-Hybrid Mount configuration
+# Lines starting with '#' are comments; uncomment an example to enable it
+
+# Directory where modules are installed
+moduledir = {moduledir}
+
+# Mount source shown in /proc/mounts, derived from root_backend when left at the default
+mountsource = {mountsource}
+
+# Root implementation: "ksu", "apatch" or "magisk"
+root_backend = {root_backend}
+
+# Enable detailed logging
+verbose = {verbose}
+
+# Extra partitions to mount besides the built-in ones, as a list or a comma separated string:
+#   partitions = ["my_product", "my_heytap"]
+#   partitions = "my_product,my_heytap"
+partitions = []
+
+# Add read-only ext4/erofs partitions mounted directly under / to partitions at boot
+auto_partitions = {auto_partitions}
+
+# Storage backend for synced modules: "tmpfs", "ext4", "erofs", "none" or "auto"
+# Set "ext4" to always use the loop image (this replaces the old force_ext4 switch)
+overlay_mode = {overlay_mode}
+
+# Override the ext4 image location, must be an absolute path under /data
+# image_path = "/data/adb/meta-hybrid/modules.img"
+
+# Run a full e2fsck on the ext4 image before every mount, not only after a failed mount
+fsck_every_boot = {fsck_every_boot}
+
+# Size cap for the tmpfs backend in MiB, 0 keeps the kernel default (half the RAM).
+# Modules larger than the cap or half of MemAvailable fall back to the ext4 image
+tmpfs_size_mb = {tmpfs_size_mb}
+
+# Hide the ext4 storage mount from userspace once mounting is done (ext4 backend only)
+enable_nuke = {enable_nuke}
+
+# Do not register any mount for KernelSU try-umount (troubleshooting only)
+disable_umount = {disable_umount}
+
+# Keep try-umount enabled even when another unmount solution (e.g. ZygiskSU enforce) is active
+allow_umount_coexistence = {allow_umount_coexistence}
+
+# Plan and log every step without mounting anything
+dry_run = {dry_run}
+
+# Mount point used for the storage backend
+hybrid_mnt_dir = {hybrid_mnt_dir}
+
+# Preferred temp root for magic mount, probed before the built-in candidates
+# tempdir = "/debug_ramdisk"
+
+# Module IDs that are never scanned or mounted, the running metamodule is always excluded
+ignored_module_ids = {ignored_module_ids}
+
+# Unmount overlays and storage recorded by an earlier run that are not part of the plan
+cleanup_orphan_mounts = {cleanup_orphan_mounts}
+
+# Rotate daemon.log when it exceeds this size in KiB, 0 disables rotation
+max_log_size_kb = {max_log_size_kb}
+
+# Log format: "text" or "json"
+log_format = {log_format}
+
+# Keep owner, mode and timestamps of module files when syncing them into storage
+preserve_sync_metadata = {preserve_sync_metadata}
+
+# Check synced files against their source before mounting: "off", "size" or "content"
+verify_sync = {verify_sync}
+
+# Force the overlay userxattr option, detected automatically when unset
+# overlay_userxattr = true
+
+# Extra overlayfs mount options passed to the kernel as-is, e.g. ["metacopy=on", "redirect_dir=on"]
+overlay_options = {overlay_options}
+
+# Give each overlay partition a writable upper layer under the storage root
+overlay_writable = {overlay_writable}
+
+# TESTING ONLY: mounts made in the private namespace disappear when the daemon exits,
+# so enabling this leaves the system booted with no modules mounted
+use_private_namespace = {use_private_namespace}
+
+# Abort the daemon if mounting runs longer than this many seconds, 0 disables the watchdog
+mount_timeout_secs = {mount_timeout_secs}
+
+# Rename the daemon to a kworker-like name
+camouflage = {camouflage}
+
+# Time budget per lifecycle point for scripts in hooks/<event>/ (pre-sync, post-sync,
+# pre-mount, post-mount, on-rollback). Hooks over budget are killed and reported
+hook_timeout_secs = {hook_timeout_secs}
+
+# Worker threads for the module scan, 0 uses one per CPU. Lower it on slow eMMC
+scan_threads = {scan_threads}
+
+# Retries for mounts failing with EBUSY, EAGAIN or ENOMEM, the delay doubles after each attempt
+[mount_retries]
+attempts = {retry_attempts}
+backoff_ms = {retry_backoff_ms}
+
+# Silo backups of config and rules, retention_days = 0 keeps them regardless of age
+[granary]
+max_backups = {max_backups}
+retention_days = {retention_days}
+
+# Default mount mode per partition: "overlay", "magic" or "ignore"
+# Precedence: per-path rule > per-module default > this table > overlay
+[partition_modes]
+# vendor = "magic"
+
+# Whether /system/<partition> must be a symlink before magic mount attaches it to /
+[partition_symlink_policy]
+# odm = true
+
+# Which module wins a contested file without a rule: the "first" contender in mount order
+# (the top overlay layer, also accepted as "highest"), the "last" one, or the
+# "alphabetical"ly first module id
+[winnowing]
+strategy = {winnowing_strategy}
+
+# Preferred module for a file contested by several modules, keyed by absolute path
+[winnowing.rules]
+# "/system/etc/hosts" = "my_hosts_module"
+"#,
+            moduledir = toml_value(&self.moduledir),
+            mountsource = toml_value(&self.mountsource),
+            root_backend = toml_value(&self.root_backend),
+            verbose = self.verbose,
+            auto_partitions = self.auto_partitions,
+            overlay_mode = toml_value(&self.overlay_mode),
+            enable_nuke = self.enable_nuke,
+            disable_umount = self.disable_umount,
+            allow_umount_coexistence = self.allow_umount_coexistence,
+            dry_run = self.dry_run,
+            hybrid_mnt_dir = toml_value(&self.hybrid_mnt_dir),
+            cleanup_orphan_mounts = self.cleanup_orphan_mounts,
+            ignored_module_ids = toml_value(&self.ignored_module_ids),
+            max_log_size_kb = self.max_log_size_kb,
+            log_format = toml_value(&self.log_format),
+            preserve_sync_metadata = self.preserve_sync_metadata,
+            verify_sync = toml_value(&self.verify_sync),
+            overlay_options = toml_value(&self.overlay_options),
+            overlay_writable = self.overlay_writable,
+            use_private_namespace = self.use_private_namespace,
+            mount_timeout_secs = self.mount_timeout_secs,
+            camouflage = self.camouflage,
+            fsck_every_boot = self.fsck_every_boot,
+            tmpfs_size_mb = self.tmpfs_size_mb,
+            hook_timeout_secs = self.hook_timeout_secs,
+            scan_threads = self.scan_threads,
+            retry_attempts = self.mount_retries.attempts,
+            retry_backoff_ms = self.mount_retries.backoff_ms,
+            max_backups = self.granary.max_backups,
+            retention_days = self.granary.retention_days,
+            winnowing_strategy = toml_value(&self.winnowing.strategy),
+        )
+    }
+
+    pub fn schema() -> Result<Vec<ConfigField>> {
+        let defaults = Self::default();
+
+        let value = serde_json::to_value(&defaults).context("failed to serialize defaults")?;
+
+        let template = defaults.to_commented_toml();
+
+        let help = template_help(&template);
+
+        let mut fields = Vec::new();
+
+        collect_fields("", &value, &help, &mut fields);
+
+        fields.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(fields)
+    }
+
+    pub fn merge_with_cli(&mut self, cli: &Cli) {
+        if let Some(dir) = &cli.moduledir {
+            self.moduledir = dir.clone();
         }
 
-        if let Some(source) = mountsource {
-            self.mountsource = source;
+        if let Some(source) = &cli.mountsource {
+            self.mountsource = source.clone();
         }
 
-        if verbose {
+        if cli.verbose {
             self.verbose = true;
         }
 
-        if !partitions.is_empty() {
-            self.partitions = partitions;
+        if !cli.partitions.is_empty() {
+            self.partitions = cli.partitions.clone();
         }
 
-        if dry_run {
+        if cli.dry_run {
             self.dry_run = true;
         }
+
+        if let Some(mode) = &cli.storage_backend {
+            self.overlay_mode = mode.clone();
+        }
+
+        if let Some(format) = cli.log_format {
+            self.log_format = format;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_winnowing_strategy_is_rejected() {
+        let err = toml::from_str::<WinnowingConfig>("strategy = \"lats\"").unwrap_err();
+
+        assert!(err.to_string().contains("winnowing.strategy"), "{err}");
+
+        let with_rules = "strategy = \"lats\"\n[rules]\n\"/system/etc/hosts\" = \"adblock\"\n";
+        let err = toml::from_str::<WinnowingConfig>(with_rules).unwrap_err();
+
+        assert!(err.to_string().contains("winnowing.strategy"), "{err}");
+    }
+
+    #[test]
+    fn legacy_and_typed_winnowing_tables_parse() {
+        let legacy: WinnowingConfig =
+            toml::from_str("\"/system/etc/hosts\" = \"adblock\"").unwrap();
+
+        assert_eq!(legacy.strategy, WinnowingStrategy::First);
+        assert_eq!(legacy.rules["/system/etc/hosts"], "adblock");
+
+        let typed: WinnowingConfig = toml::from_str(
+            "default_strategy = \"alphabetical\"\n[rules]\n\"/system/etc/hosts\" = \"adblock\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(typed.strategy, WinnowingStrategy::Alphabetical);
+        assert_eq!(typed.rules["/system/etc/hosts"], "adblock");
+    }
+
+    #[test]
+    fn unknown_winnowing_key_is_rejected() {
+        assert!(toml::from_str::<WinnowingConfig>("stratgey = \"last\"").is_err());
     }
 }
@@ -7,24 +7,34 @@ use std::{
 };
 
 use anyhow::Result;
+use procfs::process::Process;
 use rustix::mount::UnmountFlags;
 use walkdir::WalkDir;
 
 use crate::{
     conf::config,
-    core::planner::MountPlan,
+    core::{
+        granary, inventory,
+        planner::{MountPlan, OverlayOperation},
+        progress, state, storage, winnow,
+    },
     defs,
-    mount::{magic, overlay},
+    error::MountError,
+    mount::{
+        magic, overlay,
+        script::{MountScript, quote_path},
+    },
     utils,
 };
 
 pub struct ExecutionResult {
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub writable_paths: Vec<String>,
+    pub mount_reasons: HashMap<String, String>,
 }
 
 pub enum DiagnosticLevel {
-    #[allow(dead_code)]
     Info,
     Warning,
     Critical,
@@ -36,17 +46,64 @@ pub struct DiagnosticIssue {
     pub message: String,
 }
 
+impl From<inventory::ScanIssue> for DiagnosticIssue {
+    fn from(issue: inventory::ScanIssue) -> Self {
+        Self {
+            level: DiagnosticLevel::Warning,
+            context: issue.module,
+            message: issue.message,
+        }
+    }
+}
+
 fn extract_module_root(partition_path: &Path) -> Option<PathBuf> {
     partition_path.parent().map(|p| p.to_path_buf())
 }
 
+fn reserved_temp_roots(config: &config::Config) -> [&Path; 2] {
+    [
+        Path::new(&config.hybrid_mnt_dir),
+        Path::new(defs::SYSTEM_RW_DIR),
+    ]
+}
+
+fn resolve_rw_dirs(op: &OverlayOperation, provision: bool) -> (Option<PathBuf>, Option<PathBuf>) {
+    if let (Some(upper), Some(work)) = (&op.upperdir, &op.workdir) {
+        if !provision {
+            return (Some(upper.clone()), Some(work.clone()));
+        }
+
+        match std::fs::create_dir_all(upper).and_then(|_| std::fs::create_dir_all(work)) {
+            Ok(_) => return (Some(upper.clone()), Some(work.clone())),
+            Err(e) => log::warn!(
+                "Cannot provision writable layer for {}: {}. Mounting read-only.",
+                op.partition_name,
+                e
+            ),
+        }
+    }
+
+    let part_rw = Path::new(defs::SYSTEM_RW_DIR).join(&op.partition_name);
+
+    let upper = part_rw.join("upperdir");
+
+    let work = part_rw.join("workdir");
+
+    if upper.exists() && work.exists() {
+        (Some(upper), Some(work))
+    } else {
+        (None, None)
+    }
+}
+
 struct OverlayResult {
     magic_roots: Vec<PathBuf>,
     fallback_ids: Vec<String>,
+    fallback_reason: &'static str,
     success_records: Vec<(PathBuf, String)>,
 }
 
-pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
+pub fn diagnose_plan(plan: &MountPlan, config: &config::Config) -> Vec<DiagnosticIssue> {
     let mut issues = Vec::new();
 
     for op in &plan.overlay_ops {
@@ -59,6 +116,66 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
                 message: format!("Target mount point does not exist: {}", op.target),
             });
         }
+
+        // Nested mounts under the target are restored with bind mounts, so the root overlay
+        // is the only mount data string built for an op
+        let lowerdirs: Vec<String> = op
+            .lowerdirs
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+
+        let lowerdir_config = lowerdirs
+            .iter()
+            .cloned()
+            .chain(std::iter::once(op.target.clone()))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let upperdir = op.upperdir.as_ref().map(|p| p.display().to_string());
+
+        let workdir = op.workdir.as_ref().map(|p| p.display().to_string());
+
+        let extra = overlay::overlay_extra_options(
+            upperdir.as_deref(),
+            workdir.as_deref(),
+            config
+                .overlay_userxattr
+                .unwrap_or_else(|| overlay::needs_userxattr(&lowerdirs)),
+            &config.effective_overlay_options(),
+        );
+
+        if let Err(e) =
+            overlay::check_overlay_limits(&lowerdir_config, op.lowerdirs.len() + 1, &extra)
+        {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Critical,
+                context: op.partition_name.clone(),
+                message: format!(
+                    "Overlay for {} is too large: {}. The partition falls back to magic mount",
+                    op.target, e
+                ),
+            });
+        }
+    }
+
+    for mismatch in plan.analyze_type_mismatches() {
+        let entries: Vec<String> = mismatch
+            .entries
+            .iter()
+            .map(|(module, file_type)| format!("{} ({})", module, file_type))
+            .collect();
+
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Critical,
+            context: mismatch.partition.clone(),
+            message: format!(
+                "Type mismatch at /{}/{}: {}. Only one side survives the merge",
+                mismatch.partition,
+                mismatch.relative_path,
+                entries.join(", ")
+            ),
+        });
     }
 
     let all_layers: Vec<(String, &PathBuf)> = plan
@@ -73,6 +190,44 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
         })
         .collect();
 
+    if !plan.dependencies.cycle.is_empty() {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Critical,
+            context: "dependencies".to_string(),
+            message: format!(
+                "Dependency cycle among modules: {}. They are mounted in scan order",
+                plan.dependencies.cycle.join(", ")
+            ),
+        });
+    }
+
+    for missing in &plan.dependencies.missing {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: missing.module.clone(),
+            message: format!(
+                "Requires module '{}', which is {}",
+                missing.requires,
+                if missing.installed {
+                    "disabled"
+                } else {
+                    "not installed"
+                }
+            ),
+        });
+    }
+
+    if !plan.overlay_ops.is_empty() && !overlay::overlay_available() {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "overlay".to_string(),
+            message: format!(
+                "OverlayFS is unavailable on this kernel, {} partitions will fall back to magic mount",
+                plan.overlay_ops.len()
+            ),
+        });
+    }
+
     for (mod_id, layer_path) in all_layers {
         if !layer_path.exists() {
             continue;
@@ -97,45 +252,317 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
         }
     }
 
+    for warning in config.validate() {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "config".to_string(),
+            message: warning,
+        });
+    }
+
+    if !config.partition_modes.is_empty() {
+        let mut defaults: Vec<String> = config
+            .partition_modes
+            .iter()
+            .map(|(partition, mode)| format!("{}={:?}", partition, mode).to_lowercase())
+            .collect();
+
+        defaults.sort();
+
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "rules".to_string(),
+            message: format!(
+                "Mount mode precedence: per-path rule > per-module default > per-partition default ({}) > global default (overlay)",
+                defaults.join(", ")
+            ),
+        });
+    }
+
+    let last_state = state::RuntimeState::load().unwrap_or_default();
+
+    for failure in &last_state.hook_failures {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "hooks".to_string(),
+            message: format!("Last boot: hook {}", failure),
+        });
+    }
+
+    if let Some(fsck) = last_state.fsck {
+        issues.push(DiagnosticIssue {
+            level: if fsck.data_lost {
+                DiagnosticLevel::Warning
+            } else {
+                DiagnosticLevel::Info
+            },
+            context: "storage".to_string(),
+            message: format!("Last boot: {}", fsck.describe()),
+        });
+    }
+
+    if config.overlay_mode == config::OverlayMode::Auto {
+        let decision = storage::decide_auto_policy(&config.image_path());
+
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "storage".to_string(),
+            message: format!("Auto storage policy: {}", decision.reason),
+        });
+    }
+
+    for mod_id in &plan.ignored_module_ids {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: mod_id.clone(),
+            message: "Module is skipped: all of its partitions are set to ignore by rule"
+                .to_string(),
+        });
+    }
+
+    for (mod_id, assets) in &plan.asset_only_modules {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: mod_id.clone(),
+            message: format!(
+                "Module provides {} but no mount content; these are applied by the root manager, not mounted",
+                assets.join(" and ")
+            ),
+        });
+    }
+
+    let ratoon = granary::simulate_ratoon();
+
+    issues.push(DiagnosticIssue {
+        level: if ratoon.boots_until_rollback == 1 {
+            DiagnosticLevel::Warning
+        } else {
+            DiagnosticLevel::Info
+        },
+        context: "ratoon".to_string(),
+        message: format!("Rollback forecast: {}", ratoon.summary),
+    });
+
+    match config.root_backend {
+        config::RootBackend::Ksu => {}
+        config::RootBackend::Apatch => issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "backend".to_string(),
+            message: "APatch backend: KSU try-umount and sysfs nuke are unavailable, mounts stay \
+                      visible to apps unless hidden by APatch itself"
+                .to_string(),
+        }),
+        config::RootBackend::Magisk => issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "backend".to_string(),
+            message: "Magisk backend: unmount hiding is left to Magisk DenyList, try-umount and \
+                      sysfs nuke are skipped"
+                .to_string(),
+        }),
+    }
+
+    if (!plan.magic_module_paths.is_empty() || !plan.overlay_ops.is_empty())
+        && let Err(e) = utils::select_temp_dir(
+            config.tempdir.as_deref(),
+            false,
+            &reserved_temp_roots(config),
+        )
+    {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Critical,
+            context: "tempdir".to_string(),
+            message: format!("{}; magic mount cannot run", e),
+        });
+    }
+
+    for mod_id in winnow::find_shadowed_modules(plan.analyze_conflicts(), &config.winnowing) {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: mod_id,
+            message: "Module is enabled but contributes no effective files: all of its content \
+                      is overridden by other modules or winnowing rules"
+                .to_string(),
+        });
+    }
+
     issues
 }
 
+// Only mount points this daemon recorded are judged. Magisk and other KSU tooling mount with
+// the same source names, so matching on the source would sweep up their mounts too
+pub fn detect_orphan_mounts(
+    plan: &MountPlan,
+    storage_root: &Path,
+    magic_active: bool,
+    config: &config::Config,
+    previous: &state::RuntimeState,
+) -> Result<Vec<PathBuf>> {
+    let mut expected: Vec<PathBuf> = vec![storage_root.to_path_buf()];
+
+    expected.extend(plan.overlay_ops.iter().map(|op| PathBuf::from(&op.target)));
+
+    if magic_active {
+        expected.extend(
+            defs::BUILTIN_PARTITIONS
+                .iter()
+                .map(|p| Path::new("/").join(p))
+                .chain(config.partitions.iter().map(|p| Path::new("/").join(p))),
+        );
+    }
+
+    let mounts = Process::myself()?.mountinfo()?.0;
+
+    let recorded_targets = previous
+        .active_mounts
+        .iter()
+        .chain(plan.overlay_ops.iter().map(|op| &op.partition_name))
+        .map(|partition| Path::new("/").join(partition))
+        .filter(|target| granary::overlay_mounted_at(target));
+
+    let recorded_storage = Some(previous.mount_point.clone())
+        .filter(|mp| !mp.as_os_str().is_empty())
+        .filter(|mp| mounts.iter().any(|m| m.mount_point == *mp));
+
+    let mut orphans: Vec<PathBuf> = recorded_targets
+        .chain(recorded_storage)
+        .filter(|mp| !expected.iter().any(|e| mp.starts_with(e)))
+        .collect();
+
+    orphans.sort();
+
+    orphans.dedup();
+
+    Ok(orphans)
+}
+
+pub fn render_script(plan: &MountPlan, config: &config::Config) -> Result<String> {
+    let mut script = MountScript::new();
+
+    // Assumes every overlay succeeds, so no module falls back to magic mount
+    let mut magic_exclusions: HashMap<PathBuf, HashSet<String>> = plan.magic_exclusions.clone();
+
+    let overlay_options = config.effective_overlay_options();
+
+    for op in &plan.overlay_ops {
+        let lowerdir_strings: Vec<String> = op
+            .lowerdirs
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+
+        let (upper_opt, work_opt) = resolve_rw_dirs(op, false);
+
+        script.comment(&format!("overlay: {} -> {}", op.partition_name, op.target));
+
+        overlay::render_overlay(
+            &mut script,
+            &op.target,
+            &lowerdir_strings,
+            &overlay::OverlayParams {
+                upperdir: upper_opt,
+                workdir: work_opt,
+                userxattr: config.overlay_userxattr,
+                options: &overlay_options,
+                mount_source: &config.mountsource,
+            },
+        )?;
+
+        for layer_path in &op.lowerdirs {
+            if let Some(root) = extract_module_root(layer_path) {
+                magic_exclusions
+                    .entry(root)
+                    .or_default()
+                    .insert(op.partition_name.clone());
+            }
+        }
+    }
+
+    if !plan.magic_module_paths.is_empty() {
+        let tempdir = utils::select_temp_dir(
+            config.tempdir.as_deref(),
+            false,
+            &reserved_temp_roots(config),
+        )?;
+
+        script.comment(&format!("magic mount using {}", tempdir.display()));
+
+        script.push(format!("mkdir -p {}", quote_path(&tempdir)));
+
+        script.push(format!("mount -t tmpfs tmpfs {}", quote_path(&tempdir)));
+
+        magic::render_partitions(
+            &mut script,
+            &tempdir,
+            &plan.magic_module_paths,
+            &config.mountsource,
+            &config.partitions,
+            &magic_exclusions,
+            &config.partition_symlink_policy,
+        )?;
+
+        script.push(format!("umount -l {}", quote_path(&tempdir)));
+    }
+
+    Ok(script.render())
+}
+
 pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionResult> {
     let mut magic_queue = plan.magic_module_paths.clone();
 
-    let mut global_success_map: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    // Partitions already served by overlay are added to the plan's ignore exclusions below
+    let mut magic_exclusions: HashMap<PathBuf, HashSet<String>> = plan.magic_exclusions.clone();
 
     let mut final_overlay_ids = HashSet::new();
 
+    let mut mount_reasons = plan.mount_reasons.clone();
+
     plan.overlay_module_ids.iter().for_each(|id| {
         final_overlay_ids.insert(id.clone());
     });
 
     log::info!(">> Phase 1: OverlayFS Execution...");
 
+    let overlay_supported = plan.overlay_ops.is_empty() || overlay::overlay_available();
+
+    if !overlay_supported {
+        log::warn!(
+            "OverlayFS is unavailable on this kernel, routing {} partitions to magic mount",
+            plan.overlay_ops.len()
+        );
+    }
+
+    let overlay_total = plan.overlay_ops.len();
+
+    let overlay_options = config.effective_overlay_options();
+
     // Changed from par_iter() to iter() to ensure thread safety when modifying CWD
     let overlay_results: Vec<OverlayResult> = plan
         .overlay_ops
         .iter()
-        .map(|op| {
+        .enumerate()
+        .map(|(index, op)| {
+            progress::report(
+                progress::PHASE_OVERLAY,
+                None,
+                Some(&op.target),
+                index,
+                overlay_total,
+            );
+
             let lowerdir_strings: Vec<String> = op
                 .lowerdirs
                 .iter()
                 .map(|p: &PathBuf| p.display().to_string())
                 .collect();
 
-            let rw_root = Path::new(defs::SYSTEM_RW_DIR);
+            let (upper_opt, work_opt) = resolve_rw_dirs(op, true);
 
-            let part_rw = rw_root.join(&op.partition_name);
-
-            let upper = part_rw.join("upperdir");
-
-            let work = part_rw.join("workdir");
-
-            let (upper_opt, work_opt) = if upper.exists() && work.exists() {
-                (Some(upper), Some(work))
-            } else {
-                (None, None)
+            let params = overlay::OverlayParams {
+                upperdir: upper_opt,
+                workdir: work_opt,
+                userxattr: config.overlay_userxattr,
+                options: &overlay_options,
+                mount_source: &config.mountsource,
             };
 
             log::info!(
@@ -144,13 +571,43 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
                 lowerdir_strings.len()
             );
 
-            if let Err(e) = overlay::mount_overlay(
-                &op.target,
-                &lowerdir_strings,
-                work_opt,
-                upper_opt,
-                config.disable_umount,
-            ) {
+            // mount_overlay unwinds itself on failure, so every attempt starts from a clean target
+            let mounted = if overlay_supported {
+                utils::retry_mount(&op.target, &config.mount_retries, || {
+                    overlay::mount_overlay(
+                        &op.target,
+                        &lowerdir_strings,
+                        &params,
+                        config.disable_umount,
+                    )
+                })
+            } else {
+                Err(MountError::OverlayUnsupported("overlayfs is unavailable".to_string()).into())
+            };
+
+            // Magic mount stages through the same storage, so a storage fault is not retried there
+            if let Err(e) = &mounted
+                && let Some(MountError::Storage(_)) = MountError::find(e)
+            {
+                log::error!(
+                    "OverlayFS failed for {}: {:#}. Not falling back to magic mount.",
+                    op.target,
+                    e
+                );
+
+                return OverlayResult {
+                    magic_roots: Vec::new(),
+                    fallback_ids: op
+                        .lowerdirs
+                        .iter()
+                        .filter_map(|layer_path| utils::extract_module_id(layer_path))
+                        .collect(),
+                    fallback_reason: "failed:storage",
+                    success_records: Vec::new(),
+                };
+            }
+
+            if let Err(e) = mounted {
                 log::warn!(
                     "OverlayFS failed for {}: {}. Triggering fallback.",
                     op.target,
@@ -174,6 +631,10 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
                 return OverlayResult {
                     magic_roots: local_magic,
                     fallback_ids: local_fallback_ids,
+                    fallback_reason: match MountError::find(&e) {
+                        Some(MountError::OverlayUnsupported(_)) => "fallback:overlay-unsupported",
+                        _ => "fallback:overlay-failed",
+                    },
                     success_records: Vec::new(),
                 };
             }
@@ -189,6 +650,7 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             OverlayResult {
                 magic_roots: Vec::new(),
                 fallback_ids: Vec::new(),
+                fallback_reason: "",
                 success_records: successes,
             }
         })
@@ -199,24 +661,56 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
 
         for id in res.fallback_ids {
             final_overlay_ids.remove(&id);
+
+            mount_reasons.insert(id, res.fallback_reason.to_string());
         }
 
         for (root, partition) in res.success_records {
-            global_success_map
-                .entry(root)
-                .or_default()
-                .insert(partition);
+            magic_exclusions.entry(root).or_default().insert(partition);
         }
     }
 
-    magic_queue.sort();
+    progress::report(
+        progress::PHASE_OVERLAY,
+        None,
+        None,
+        overlay_total,
+        overlay_total,
+    );
+
+    // Dependencies merge first, so the plan's module order decides the queue rather than the path
+    magic_queue.sort_by(|a, b| {
+        plan.module_rank(a)
+            .cmp(&plan.module_rank(b))
+            .then_with(|| a.cmp(b))
+    });
 
     magic_queue.dedup();
 
+    state::RuntimeState::record_phase(state::PHASE_OVERLAY_DONE);
+
     let mut final_magic_ids = Vec::new();
 
-    if !magic_queue.is_empty() {
-        let tempdir = utils::select_temp_dir()?;
+    let mut writable_paths = Vec::new();
+
+    let tempdir = if magic_queue.is_empty() {
+        None
+    } else {
+        match utils::select_temp_dir(
+            config.tempdir.as_deref(),
+            true,
+            &reserved_temp_roots(config),
+        ) {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                log::error!("[CRITICAL] {:#}. Skipping magic mount.", e);
+
+                None
+            }
+        }
+    };
+
+    if let Some(tempdir) = tempdir {
         let _ = crate::try_umount::TMPFS.set(tempdir.to_string_lossy().to_string());
 
         for path in &magic_queue {
@@ -234,14 +728,21 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             std::fs::create_dir_all(&tempdir)?;
         }
 
-        utils::mount_tmpfs(&tempdir, "tmpfs")?;
+        utils::retry_mount("magic mount tmpfs", &config.mount_retries, || {
+            utils::mount_tmpfs(&tempdir, "tmpfs")
+        })?;
+
+        let magic_total = final_magic_ids.len();
+
+        progress::report(progress::PHASE_MAGIC, None, None, 0, magic_total);
 
         if let Err(e) = magic::mount_partitions(
             &tempdir,
             &magic_queue,
             &config.mountsource,
             &config.partitions,
-            global_success_map,
+            magic_exclusions,
+            &config.partition_symlink_policy,
             config.disable_umount,
         ) {
             log::error!("Magic Mount critical failure: {:#}", e);
@@ -249,9 +750,25 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             final_magic_ids.clear();
         }
 
+        writable_paths = magic::take_writable_paths()
+            .into_iter()
+            .map(|p| p.display().to_string())
+            .collect();
+
+        if !writable_paths.is_empty() {
+            log::warn!(
+                "!! {} magic mounted paths could not be made read-only",
+                writable_paths.len()
+            );
+        }
+
         let _ = rustix::mount::unmount(&tempdir, UnmountFlags::DETACH);
+
+        progress::report(progress::PHASE_MAGIC, None, None, magic_total, magic_total);
     }
 
+    state::RuntimeState::record_phase(state::PHASE_MAGIC_DONE);
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     if !config.disable_umount
         && let Err(e) = crate::try_umount::commit()
@@ -272,5 +789,7 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
     Ok(ExecutionResult {
         overlay_module_ids: result_overlay,
         magic_module_ids: result_magic,
+        writable_paths,
+        mount_reasons,
     })
 }
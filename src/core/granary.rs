@@ -4,19 +4,27 @@
 use std::{
     fs,
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail};
+use procfs::process::Process;
+use rustix::mount::{UnmountFlags, unmount};
 use serde::{Deserialize, Serialize};
 
-use crate::{conf::config::Config, defs, utils};
+use crate::{
+    conf::config::Config,
+    core::{planner::PlanSnapshot, state::RuntimeState},
+    defs, utils,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Silo {
     pub id: String,
     pub timestamp: u64,
+    #[serde(default)]
+    pub sequence: u64,
     pub label: String,
     pub reason: String,
     pub config_snapshot: Config,
@@ -26,13 +34,92 @@ pub struct Silo {
     pub raw_state: Option<String>,
 }
 
+#[derive(Serialize, Debug)]
+pub struct SiloDiffEntry {
+    pub key: String,
+    pub current: Option<serde_json::Value>,
+    pub snapshot: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SiloDiff {
+    pub silo_id: String,
+    pub label: String,
+    pub timestamp: u64,
+    pub config_changes: Vec<SiloDiffEntry>,
+    pub restores_state: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RatoonForecast {
+    pub count: u8,
+    pub threshold: u8,
+    pub boots_until_rollback: u8,
+    pub restore_target: Option<String>,
+    pub summary: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RepairEntry {
+    pub path: String,
+    pub problem: String,
+    pub action: String,
+    pub backup: Option<String>,
+    pub repaired: bool,
+}
+
 const RATOON_COUNTER_FILE: &str = "/data/adb/meta-hybrid/ratoon_counter";
+pub const RATOON_THRESHOLD: u8 = 3;
 
 const RATOON_RESCUE_NOTICE: &str = "/data/adb/meta-hybrid/rescue_notice";
 
 const GRANARY_DIR: &str = "/data/adb/meta-hybrid/granary";
 
-pub fn engage_ratoon_protocol() -> Result<()> {
+const PLAN_SNAPSHOT_DIR: &str = "/data/adb/meta-hybrid/granary/plans";
+
+pub fn ratoon_count() -> u8 {
+    fs::read_to_string(RATOON_COUNTER_FILE)
+        .ok()
+        .and_then(|content| content.trim().parse::<u8>().ok())
+        .unwrap_or(0)
+}
+
+// Mirrors engage_ratoon_protocol without touching the counter or the silos
+pub fn simulate_ratoon() -> RatoonForecast {
+    let count = ratoon_count();
+
+    let boots_until_rollback = RATOON_THRESHOLD.saturating_sub(count).max(1);
+
+    let latest = list_silos().ok().and_then(|silos| silos.into_iter().next());
+
+    let action = match &latest {
+        Some(silo) => format!("restores {} ({})", silo.id, silo.label),
+        None => "disables all modules, no silo is available to restore".to_string(),
+    };
+
+    let summary = if boots_until_rollback == 1 {
+        format!(
+            "counter={}/{}, the next failed boot {}",
+            count, RATOON_THRESHOLD, action
+        )
+    } else {
+        format!(
+            "counter={}/{}, {} more failed boots before rollback, which {}",
+            count, RATOON_THRESHOLD, boots_until_rollback, action
+        )
+    };
+
+    RatoonForecast {
+        count,
+        threshold: RATOON_THRESHOLD,
+        boots_until_rollback,
+        restore_target: latest.map(|silo| silo.id),
+        summary,
+    }
+}
+
+// Returns whether a rollback was performed on this boot
+pub fn engage_ratoon_protocol() -> Result<bool> {
     let path = Path::new(RATOON_COUNTER_FILE);
 
     let mut count = 0;
@@ -58,8 +145,13 @@ pub fn engage_ratoon_protocol() -> Result<()> {
 
     log::info!(">> Ratoon Protocol: Boot counter at {}", count);
 
-    if count >= 3 {
-        log::error!(">> RATOON TRIGGERED: Detected potential bootloop (3 failed boots).");
+    let triggered = count >= RATOON_THRESHOLD;
+
+    if triggered {
+        log::error!(
+            ">> RATOON TRIGGERED: Detected potential bootloop ({} failed boots).",
+            count
+        );
 
         log::warn!(">> Executing emergency rollback from Granary...");
 
@@ -85,7 +177,7 @@ pub fn engage_ratoon_protocol() -> Result<()> {
                     e
                 );
 
-                disable_all_modules()?;
+                disable_all_modules(Path::new(defs::MODULES_DIR))?;
 
                 // Also reset counter to avoid infinite loop of failing restores
                 let _ = fs::remove_file(path);
@@ -93,6 +185,16 @@ pub fn engage_ratoon_protocol() -> Result<()> {
         }
     }
 
+    Ok(triggered)
+}
+
+pub fn reset_ratoon_counter() -> Result<()> {
+    let path = Path::new(RATOON_COUNTER_FILE);
+
+    if path.exists() {
+        fs::remove_file(path).context("Failed to reset Ratoon counter")?;
+    }
+
     Ok(())
 }
 
@@ -115,7 +217,22 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
 
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
 
-    let id = format!("silo_{}", now);
+    let existing = list_silos().unwrap_or_default();
+
+    // Ordering relies on the sequence, since the RTC may be wrong early in boot
+    let sequence = existing.iter().map(|s| s.sequence).max().unwrap_or(0) + 1;
+
+    if let Some(newest) = existing.iter().map(|s| s.timestamp).max()
+        && now < newest
+    {
+        log::warn!(
+            "Granary: Clock skew detected (now {} < newest silo {}). Ordering by sequence.",
+            now,
+            newest
+        );
+    }
+
+    let id = format!("silo_{}_{}", now, sequence);
 
     let raw_config = fs::read_to_string(crate::conf::config::CONFIG_FILE_DEFAULT).ok();
 
@@ -124,6 +241,7 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
     let silo = Silo {
         id: id.clone(),
         timestamp: now,
+        sequence,
         label: label.to_string(),
         reason: reason.to_string(),
         config_snapshot: config.clone(),
@@ -165,7 +283,11 @@ pub fn list_silos() -> Result<Vec<Silo>> {
         }
     }
 
-    silos.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    silos.sort_by(|a, b| {
+        b.sequence
+            .cmp(&a.sequence)
+            .then_with(|| b.timestamp.cmp(&a.timestamp))
+    });
 
     Ok(silos)
 }
@@ -184,7 +306,7 @@ pub fn delete_silo(id: &str) -> Result<()> {
     }
 }
 
-pub fn restore_silo(id: &str) -> Result<()> {
+fn load_silo(id: &str) -> Result<Silo> {
     let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", id));
 
     if !file_path.exists() {
@@ -195,6 +317,97 @@ pub fn restore_silo(id: &str) -> Result<()> {
 
     let silo: Silo = serde_json::from_str(&content)?;
 
+    Ok(silo)
+}
+
+pub fn diff_silo(id: &str, current: &Config) -> Result<SiloDiff> {
+    let silo = load_silo(id)?;
+
+    // Prefer the raw snapshot since that is what restore_silo actually writes back
+    let snapshot_config = silo
+        .raw_config
+        .as_deref()
+        .and_then(|raw| toml::from_str::<Config>(raw).ok())
+        .unwrap_or_else(|| silo.config_snapshot.clone());
+
+    let current_value = serde_json::to_value(current)?;
+
+    let snapshot_value = serde_json::to_value(&snapshot_config)?;
+
+    let empty = serde_json::Map::new();
+
+    let current_map = current_value.as_object().unwrap_or(&empty);
+
+    let snapshot_map = snapshot_value.as_object().unwrap_or(&empty);
+
+    let mut keys: Vec<&String> = current_map.keys().chain(snapshot_map.keys()).collect();
+
+    keys.sort();
+
+    keys.dedup();
+
+    let config_changes = keys
+        .into_iter()
+        .filter_map(|key| {
+            let cur = current_map.get(key);
+
+            let snap = snapshot_map.get(key);
+
+            if cur == snap {
+                return None;
+            }
+
+            Some(SiloDiffEntry {
+                key: key.clone(),
+                current: cur.cloned(),
+                snapshot: snap.cloned(),
+            })
+        })
+        .collect();
+
+    Ok(SiloDiff {
+        silo_id: silo.id,
+        label: silo.label,
+        timestamp: silo.timestamp,
+        config_changes,
+        restores_state: silo.raw_state.is_some(),
+    })
+}
+
+fn plan_snapshot_path(name: &str) -> Result<std::path::PathBuf> {
+    utils::validate_module_id(name)
+        .with_context(|| format!("Invalid plan snapshot name: {}", name))?;
+
+    Ok(Path::new(PLAN_SNAPSHOT_DIR).join(format!("{}.json", name)))
+}
+
+pub fn save_plan_snapshot(snapshot: &PlanSnapshot) -> Result<()> {
+    let file_path = plan_snapshot_path(&snapshot.name)?;
+
+    fs::create_dir_all(PLAN_SNAPSHOT_DIR).context("Failed to create plan snapshot dir")?;
+
+    let json = serde_json::to_string_pretty(snapshot)?;
+
+    utils::atomic_write(&file_path, json)
+}
+
+pub fn load_plan_snapshot(name: &str) -> Result<PlanSnapshot> {
+    let file_path = plan_snapshot_path(name)?;
+
+    if !file_path.exists() {
+        bail!("Plan snapshot {} not found", name);
+    }
+
+    let content = fs::read_to_string(&file_path)?;
+
+    let snapshot: PlanSnapshot = serde_json::from_str(&content)?;
+
+    Ok(snapshot)
+}
+
+pub fn restore_silo(id: &str) -> Result<()> {
+    let silo = load_silo(id)?;
+
     log::info!(">> Restoring Silo: {} ({})", silo.id, silo.label);
 
     if let Some(raw) = &silo.raw_config {
@@ -278,20 +491,273 @@ fn prune_silos(config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn disable_all_modules() -> Result<()> {
-    let modules_dir = Path::new(defs::MODULES_DIR);
+pub fn disable_all_modules(modules_dir: &Path) -> Result<usize> {
+    let mut toggled = 0;
 
     if modules_dir.exists() {
         for entry in fs::read_dir(modules_dir)? {
             let entry = entry?;
 
-            let disable_path = entry.path().join("disable");
+            let disable_path = entry.path().join(defs::DISABLE_FILE_NAME);
 
-            if !disable_path.exists() {
+            if entry.path().is_dir() && !disable_path.exists() {
                 fs::File::create(disable_path)?;
+
+                toggled += 1;
             }
         }
     }
 
-    Ok(())
+    Ok(toggled)
+}
+
+pub fn enable_all_modules(modules_dir: &Path) -> Result<usize> {
+    let mut toggled = 0;
+
+    if modules_dir.exists() {
+        for entry in fs::read_dir(modules_dir)? {
+            let entry = entry?;
+
+            let disable_path = entry.path().join(defs::DISABLE_FILE_NAME);
+
+            if disable_path.exists() {
+                fs::remove_file(disable_path)?;
+
+                toggled += 1;
+            }
+        }
+    }
+
+    Ok(toggled)
+}
+
+// Tears down what the daemon built so the next boot starts from scratch. The config, user
+// rules and modules themselves are left alone
+pub fn clean(config: &Config, keep_silos: bool) -> Result<Vec<String>> {
+    let state = RuntimeState::load().unwrap_or_default();
+
+    let targets: Vec<PathBuf> = state
+        .active_mounts
+        .iter()
+        .map(|partition| Path::new("/").join(partition))
+        .filter(|target| overlay_mounted_at(target))
+        .collect();
+
+    let storage_mounted =
+        !state.mount_point.as_os_str().is_empty() && utils::is_mounted(&state.mount_point);
+
+    let mut actions = Vec::new();
+
+    for target in &targets {
+        match unmount(target, UnmountFlags::DETACH) {
+            Ok(_) => actions.push(format!("unmounted {}", target.display())),
+            Err(e) => log::warn!("Failed to unmount {}: {}", target.display(), e),
+        }
+    }
+
+    if storage_mounted {
+        unmount(&state.mount_point, UnmountFlags::DETACH).with_context(|| {
+            format!("Failed to unmount storage {}", state.mount_point.display())
+        })?;
+
+        actions.push(format!("unmounted storage {}", state.mount_point.display()));
+    }
+
+    let image_path = config.image_path();
+
+    let files = [
+        image_path.clone(),
+        image_path.with_extension("erofs"),
+        PathBuf::from(defs::SYNC_MANIFEST_FILE),
+        PathBuf::from(defs::STATE_FILE),
+    ];
+
+    for path in files {
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+
+            actions.push(format!("removed {}", path.display()));
+        }
+    }
+
+    if ratoon_count() > 0 {
+        reset_ratoon_counter()?;
+
+        actions.push("reset the Ratoon counter".to_string());
+    }
+
+    if !keep_silos && Path::new(GRANARY_DIR).exists() {
+        fs::remove_dir_all(GRANARY_DIR).context("Failed to remove granary silos")?;
+
+        actions.push("removed granary silos".to_string());
+    }
+
+    for action in &actions {
+        log::info!("Clean: {}", action);
+    }
+
+    Ok(actions)
+}
+
+// The newest mount on a path is the visible one, a stock mount underneath is never touched
+pub fn overlay_mounted_at(target: &Path) -> bool {
+    Process::myself()
+        .and_then(|p| p.mountinfo())
+        .map(|mounts| {
+            mounts
+                .0
+                .iter()
+                .rev()
+                .find(|m| m.mount_point == target)
+                .is_some_and(|m| m.fs_type == "overlay")
+        })
+        .unwrap_or(false)
+}
+
+// Config, runtime state, user rules and the sync manifest. A corrupt file is moved aside to
+// <name>.corrupt-<timestamp>, then the config is restored from the newest silo that still
+// parses (or the default template) and the rest is dropped, since it is rebuilt on demand
+pub fn repair_files(config_path: &Path, apply: bool) -> Result<Vec<RepairEntry>> {
+    let mut entries = Vec::new();
+
+    if let Some(problem) = corrupt_file(config_path, |c| {
+        toml::from_str::<Config>(c)
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }) {
+        let (source, content) = match latest_valid_config() {
+            Some((silo_id, raw)) => (format!("restored from silo {}", silo_id), raw),
+            None => (
+                "regenerated from defaults".to_string(),
+                Config::default().to_commented_toml(),
+            ),
+        };
+
+        entries.push(repair_entry(config_path, problem, source, apply, |path| {
+            utils::atomic_write(path, &content)
+        })?);
+    }
+
+    let json_files = [
+        Path::new(defs::STATE_FILE),
+        Path::new(defs::SYNC_MANIFEST_FILE),
+    ];
+
+    for path in json_files {
+        if let Some(problem) = corrupt_file(path, |c| {
+            serde_json::from_str::<serde_json::Value>(c)
+                .map(drop)
+                .map_err(|e| e.to_string())
+        }) {
+            entries.push(repair_entry(
+                path,
+                problem,
+                "removed, rebuilt on the next boot".to_string(),
+                apply,
+                |_| Ok(()),
+            )?);
+        }
+    }
+
+    if let Ok(rules) = fs::read_dir(defs::USER_RULES_DIR) {
+        let mut paths: Vec<_> = rules
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("json"))
+            .collect();
+
+        paths.sort();
+
+        for path in paths {
+            if let Some(problem) = corrupt_file(&path, |c| {
+                serde_json::from_str::<crate::core::inventory::ModuleRules>(c)
+                    .map(drop)
+                    .map_err(|e| e.to_string())
+            }) {
+                entries.push(repair_entry(
+                    &path,
+                    problem,
+                    "removed, the module falls back to its own rules".to_string(),
+                    apply,
+                    |_| Ok(()),
+                )?);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+fn corrupt_file<F>(path: &Path, parse: F) -> Option<String>
+where
+    F: Fn(&str) -> std::result::Result<(), String>,
+{
+    if !path.exists() {
+        return None;
+    }
+
+    match fs::read(path) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(content) => parse(&content).err(),
+            Err(_) => Some("not valid UTF-8".to_string()),
+        },
+        Err(e) => Some(format!("unreadable: {}", e)),
+    }
+}
+
+fn latest_valid_config() -> Option<(String, String)> {
+    list_silos().ok()?.into_iter().find_map(|silo| {
+        let raw = match silo.raw_config {
+            Some(raw) if toml::from_str::<Config>(&raw).is_ok() => raw,
+            _ => toml::to_string_pretty(&silo.config_snapshot).ok()?,
+        };
+
+        Some((silo.id, raw))
+    })
+}
+
+fn repair_entry<F>(
+    path: &Path,
+    problem: String,
+    action: String,
+    apply: bool,
+    rebuild: F,
+) -> Result<RepairEntry>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let mut entry = RepairEntry {
+        path: path.display().to_string(),
+        problem,
+        action,
+        backup: None,
+        repaired: false,
+    };
+
+    if !apply {
+        return Ok(entry);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut backup = path.as_os_str().to_owned();
+
+    backup.push(format!(".corrupt-{}", now));
+
+    fs::rename(path, &backup)
+        .with_context(|| format!("Failed to move corrupt {} aside", path.display()))?;
+
+    rebuild(path).with_context(|| format!("Failed to rebuild {}", path.display()))?;
+
+    log::info!("Repaired {}: {}", entry.path, entry.action);
+
+    entry.backup = Some(Path::new(&backup).display().to_string());
+
+    entry.repaired = true;
+
+    Ok(entry)
 }
@@ -0,0 +1,123 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::defs;
+
+pub const PRE_SYNC: &str = "pre-sync";
+pub const POST_SYNC: &str = "post-sync";
+pub const PRE_MOUNT: &str = "pre-mount";
+pub const POST_MOUNT: &str = "post-mount";
+pub const ON_ROLLBACK: &str = "on-rollback";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static FAILURES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn scripts(event: &str) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(Path::new(defs::HOOKS_DIR).join(event)) else {
+        return Vec::new();
+    };
+
+    let mut scripts: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+
+    scripts.sort();
+
+    scripts
+}
+
+// Every script of one event shares the timeout, so a lifecycle point never holds up boot longer
+// than that. A failing or timed out hook is only a warning
+pub fn run(event: &str, timeout_secs: u64) {
+    let scripts = scripts(event);
+
+    if scripts.is_empty() {
+        return;
+    }
+
+    log::info!(">> Hooks: running {} {} scripts", scripts.len(), event);
+
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+
+    for script in scripts {
+        let name = script
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if let Err(e) = run_script(event, &script, deadline) {
+            let message = format!("{}/{}: {:#}", event, name, e);
+
+            log::warn!("!! Hook failed: {}", message);
+
+            if let Ok(mut failures) = FAILURES.lock() {
+                failures.push(message);
+            }
+        }
+    }
+}
+
+fn run_script(event: &str, script: &Path, deadline: Instant) -> Result<()> {
+    fs::create_dir_all(defs::RUN_DIR).context("Failed to create run directory")?;
+
+    // Output goes to a file, a pipe kept open by a backgrounded grandchild would block the read
+    let log_path = Path::new(defs::RUN_DIR).join(format!("hook-{}.log", event));
+
+    let log_file = fs::File::create(&log_path).context("Failed to create hook log")?;
+
+    let mut child = Command::new("sh")
+        .arg(script)
+        .env("META_HYBRID_EVENT", event)
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file)
+        .spawn()
+        .context("Failed to start hook")?;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+
+            let _ = child.wait();
+
+            break None;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    for line in fs::read_to_string(&log_path).unwrap_or_default().lines() {
+        log::info!("[hook {}] {}", event, line);
+    }
+
+    match status {
+        None => bail!("timed out, killed"),
+        Some(status) if !status.success() => bail!("exited with {}", status),
+        Some(_) => Ok(()),
+    }
+}
+
+pub fn take_failures() -> Vec<String> {
+    FAILURES
+        .lock()
+        .map(|mut failures| std::mem::take(&mut *failures))
+        .unwrap_or_default()
+}
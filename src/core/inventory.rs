@@ -10,8 +10,9 @@ use std::{
 use anyhow::Result;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
-use crate::{conf::config, defs};
+use crate::{conf::config, defs, utils};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
@@ -22,16 +23,34 @@ pub enum MountMode {
     Ignore,
 }
 
+impl MountMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MountMode::Overlay => "overlay",
+            MountMode::Magic => "magic",
+            MountMode::Ignore => "ignore",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ModuleRules {
     #[serde(default)]
     pub default_mode: MountMode,
     #[serde(default)]
     pub paths: HashMap<String, MountMode>,
+    #[serde(skip)]
+    pub default_mode_set: bool,
+}
+
+fn declares_default_mode(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(content)
+        .map(|v| v.get("default_mode").is_some())
+        .unwrap_or(false)
 }
 
 impl ModuleRules {
-    pub fn load(module_dir: &Path, module_id: &str) -> Self {
+    pub fn load(module_dir: &Path, module_id: &str, problems: &mut Vec<String>) -> Self {
         let mut rules = ModuleRules::default();
 
         let internal_config = module_dir.join("hybrid_rules.json");
@@ -39,14 +58,18 @@ impl ModuleRules {
         if internal_config.exists() {
             match fs::read_to_string(&internal_config) {
                 Ok(content) => match serde_json::from_str::<ModuleRules>(&content) {
-                    Ok(r) => rules = r,
-                    Err(e) => log::warn!("Failed to parse rules for module '{}': {}", module_id, e),
+                    Ok(r) => {
+                        rules = r;
+
+                        rules.default_mode_set = declares_default_mode(&content);
+                    }
+                    Err(e) => problems.push(format!("Failed to parse hybrid_rules.json: {}", e)),
                 },
-                Err(e) => log::warn!("Failed to read rule file for '{}': {}", module_id, e),
+                Err(e) => problems.push(format!("Failed to read hybrid_rules.json: {}", e)),
             }
         }
 
-        let user_rules_dir = Path::new("/data/adb/meta-hybrid/rules");
+        let user_rules_dir = Path::new(defs::USER_RULES_DIR);
 
         let user_config = user_rules_dir.join(format!("{}.json", module_id));
 
@@ -56,11 +79,13 @@ impl ModuleRules {
                     Ok(user_rules) => {
                         rules.default_mode = user_rules.default_mode;
 
+                        rules.default_mode_set = declares_default_mode(&content);
+
                         rules.paths.extend(user_rules.paths);
                     }
-                    Err(e) => log::warn!("Failed to parse user rules for '{}': {}", module_id, e),
+                    Err(e) => problems.push(format!("Failed to parse user rules: {}", e)),
                 },
-                Err(e) => log::warn!("Failed to read user rule file for '{}': {}", module_id, e),
+                Err(e) => problems.push(format!("Failed to read user rules: {}", e)),
             }
         }
 
@@ -74,6 +99,98 @@ impl ModuleRules {
 
         self.default_mode.clone()
     }
+
+    // Precedence: per-path rule > per-module default > per-partition config default > global default
+    pub fn base_partition_mode(
+        &self,
+        partition: &str,
+        partition_default: Option<&MountMode>,
+    ) -> MountMode {
+        if !self.default_mode_set
+            && !self.paths.contains_key(partition)
+            && let Some(mode) = partition_default
+        {
+            return mode.clone();
+        }
+
+        self.get_mode(partition)
+    }
+
+    pub fn get_partition_mode(
+        &self,
+        partition: &str,
+        partition_default: Option<&MountMode>,
+    ) -> MountMode {
+        let mode = self.base_partition_mode(partition, partition_default);
+
+        if mode != MountMode::Overlay {
+            return mode;
+        }
+
+        // A lowerdir cannot be split, so a magic subtree pulls its whole partition into magic
+        let prefix = format!("{}/", partition);
+
+        let has_magic_subtree = self
+            .paths
+            .iter()
+            .any(|(path, mode)| path.starts_with(&prefix) && *mode == MountMode::Magic);
+
+        if has_magic_subtree {
+            MountMode::Magic
+        } else {
+            mode
+        }
+    }
+
+    // Names the setting get_partition_mode settled on, e.g. `rule:system/lib=magic`
+    pub fn partition_mode_reason(
+        &self,
+        partition: &str,
+        partition_default: Option<&MountMode>,
+    ) -> String {
+        let mode = self.get_partition_mode(partition, partition_default);
+
+        if mode != self.base_partition_mode(partition, partition_default) {
+            let prefix = format!("{}/", partition);
+
+            let mut subtrees: Vec<&String> = self
+                .paths
+                .iter()
+                .filter(|(path, rule)| path.starts_with(&prefix) && **rule == MountMode::Magic)
+                .map(|(path, _)| path)
+                .collect();
+
+            subtrees.sort();
+
+            if let Some(path) = subtrees.first() {
+                return format!("rule:{}=magic", path);
+            }
+        }
+
+        if let Some(rule) = self.paths.get(partition) {
+            return format!("rule:{}={}", partition, rule.as_str());
+        }
+
+        if self.default_mode_set {
+            format!("module-default:{}", mode.as_str())
+        } else if partition_default.is_some() {
+            format!("partition:{}={}", partition, mode.as_str())
+        } else {
+            format!("default:{}", mode.as_str())
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanIssue {
+    pub module: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    pub modules: Vec<Module>,
+    pub issues: Vec<ScanIssue>,
 }
 
 #[derive(Debug, Clone)]
@@ -81,51 +198,209 @@ pub struct Module {
     pub id: String,
     pub source_path: PathBuf,
     pub rules: ModuleRules,
+    pub requires: Vec<String>,
+    pub non_mount_assets: Vec<&'static str>,
 }
 
-pub fn scan(source_dir: &Path, _config: &config::Config) -> Result<Vec<Module>> {
-    if !source_dir.exists() {
-        return Ok(Vec::new());
+// Applied by the root manager itself rather than mounted, so a module shipping only these is
+// still active even though it never shows up in the mount plan
+const NON_MOUNT_ASSETS: [&str; 2] = ["system.prop", "sepolicy.rule"];
+
+fn find_non_mount_assets(module_dir: &Path) -> Vec<&'static str> {
+    NON_MOUNT_ASSETS
+        .into_iter()
+        .filter(|name| module_dir.join(name).is_file())
+        .collect()
+}
+
+// Deep enough for modules/foo/modules/bar/module.prop, shallow enough to stay cheap at boot
+const NESTED_MODULE_MAX_DEPTH: usize = 3;
+
+// Packaging mistakes that still mount, just at the wrong place: a partition tree nested in
+// itself (system/system) or another module unpacked inside this one
+fn find_nesting_mistakes(module_dir: &Path, config: &config::Config) -> Vec<String> {
+    let mut problems: Vec<String> = defs::BUILTIN_PARTITIONS
+        .iter()
+        .copied()
+        .chain(config.partitions.iter().map(String::as_str))
+        .filter(|partition| module_dir.join(partition).join(partition).is_dir())
+        .map(|partition| {
+            format!(
+                "{0}/{0} nests a whole partition tree, its files would land under /{0}/{0}",
+                partition
+            )
+        })
+        .collect();
+
+    problems.extend(
+        WalkDir::new(module_dir)
+            .min_depth(2)
+            .max_depth(NESTED_MODULE_MAX_DEPTH)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.file_type().is_file() && e.file_name() == "module.prop")
+            .filter_map(|e| {
+                e.path()
+                    .parent()?
+                    .strip_prefix(module_dir)
+                    .ok()
+                    .map(|nested| {
+                        format!(
+                            "{} holds another module.prop, likely unpacked into the wrong directory",
+                            nested.display()
+                        )
+                    })
+            }),
+    );
+
+    problems
+}
+
+fn read_requires(module_dir: &Path) -> Vec<String> {
+    utils::read_prop_line(module_dir.join("module.prop"), "requires")
+        .ok()
+        .flatten()
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn is_reserved_id(id: &str, config: &config::Config) -> bool {
+    utils::self_module_id() == Some(id) || config.ignored_module_ids.iter().any(|i| i == id)
+}
+
+fn is_filtered_out(id: &str, config: &config::Config) -> bool {
+    (!config.only_modules.is_empty() && !config.only_modules.iter().any(|i| i == id))
+        || config.except_modules.iter().any(|i| i == id)
+}
+
+pub fn unknown_filter_ids(config: &config::Config) -> Vec<&str> {
+    config
+        .only_modules
+        .iter()
+        .chain(config.except_modules.iter())
+        .map(|id| id.as_str())
+        .filter(|id| !config.moduledir.join(id).is_dir())
+        .collect()
+}
+
+pub fn scan(source_dir: &Path, config: &config::Config) -> Result<Vec<Module>> {
+    let report = scan_report(source_dir, config)?;
+
+    for issue in &report.issues {
+        log::warn!("Module '{}': {}", issue.module, issue.message);
     }
 
-    let dir_entries = fs::read_dir(source_dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    Ok(report.modules)
+}
 
-    let mut modules: Vec<Module> = dir_entries
-        .into_par_iter()
-        .filter_map(|entry| {
-            let path = entry.path();
+// Rule file and packaging problems are reported but keep the module, it still mounts with
+// the rules that did load
+fn scan_entry(
+    entry: std::io::Result<fs::DirEntry>,
+    source_dir: &Path,
+    config: &config::Config,
+) -> Vec<Result<Module, ScanIssue>> {
+    let entry = match entry {
+        Ok(entry) => entry,
+        Err(e) => {
+            return vec![Err(ScanIssue {
+                module: source_dir.display().to_string(),
+                message: format!("Failed to read directory entry: {}", e),
+            })];
+        }
+    };
 
-            if !path.is_dir() {
-                return None;
-            }
+    let path = entry.path();
 
-            let id = entry.file_name().to_string_lossy().to_string();
+    let id = entry.file_name().to_string_lossy().to_string();
 
-            if matches!(
-                id.as_str(),
-                "meta-hybrid" | "lost+found" | ".git" | ".idea" | ".vscode"
-            ) {
-                return None;
-            }
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => return Vec::new(),
+        Err(e) => {
+            return vec![Err(ScanIssue {
+                module: id,
+                message: format!("Failed to read metadata: {}", e),
+            })];
+        }
+    }
 
-            if path.join(defs::DISABLE_FILE_NAME).exists()
-                || path.join(defs::REMOVE_FILE_NAME).exists()
-                || path.join(defs::SKIP_MOUNT_FILE_NAME).exists()
-            {
-                return None;
-            }
+    if is_reserved_id(&id, config) || is_filtered_out(&id, config) {
+        return Vec::new();
+    }
+
+    if path.join(defs::DISABLE_FILE_NAME).exists()
+        || path.join(defs::REMOVE_FILE_NAME).exists()
+        || path.join(defs::SKIP_MOUNT_FILE_NAME).exists()
+    {
+        return Vec::new();
+    }
+
+    let mut problems = Vec::new();
+
+    let rules = ModuleRules::load(&path, &id, &mut problems);
 
-            let rules = ModuleRules::load(&path, &id);
+    problems.extend(find_nesting_mistakes(&path, config));
 
-            Some(Module {
-                id,
-                source_path: path,
-                rules,
+    let mut results: Vec<Result<Module, ScanIssue>> = problems
+        .into_iter()
+        .map(|message| {
+            Err(ScanIssue {
+                module: id.clone(),
+                message,
             })
         })
         .collect();
 
-    modules.sort_by(|a, b| b.id.cmp(&a.id));
+    let requires = read_requires(&path);
+
+    let non_mount_assets = find_non_mount_assets(&path);
+
+    results.push(Ok(Module {
+        id,
+        source_path: path,
+        rules,
+        requires,
+        non_mount_assets,
+    }));
+
+    results
+}
+
+pub fn scan_report(source_dir: &Path, config: &config::Config) -> Result<ScanReport> {
+    if !source_dir.exists() {
+        return Ok(ScanReport::default());
+    }
+
+    let dir_entries: Vec<_> = fs::read_dir(source_dir)?.collect();
+
+    let results: Vec<Result<Module, ScanIssue>> =
+        utils::with_thread_limit(config.scan_threads, || {
+            dir_entries
+                .into_par_iter()
+                .flat_map_iter(|entry| scan_entry(entry, source_dir, config))
+                .collect()
+        });
+
+    let mut report = ScanReport::default();
+
+    for result in results {
+        match result {
+            Ok(module) => report.modules.push(module),
+            Err(issue) => report.issues.push(issue),
+        }
+    }
+
+    report.modules.sort_by(|a, b| b.id.cmp(&a.id));
+
+    report.issues.sort_by(|a, b| a.module.cmp(&b.module));
 
-    Ok(modules)
+    Ok(report)
 }
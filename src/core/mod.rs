@@ -3,24 +3,37 @@
 
 pub mod executor;
 pub mod granary;
+pub mod hooks;
 pub mod inventory;
 pub mod modules;
 pub mod planner;
+pub mod progress;
 pub mod state;
 pub mod storage;
 pub mod sync;
+pub mod watch;
+pub mod watchdog;
 pub mod winnow;
 
-use std::path::Path;
+use std::{collections::HashSet, path::Path};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 
-use crate::{conf::config::Config, try_umount};
+use crate::{
+    conf::config::{Config, VerifySync},
+    error::MountError,
+    mount::overlay,
+    try_umount, utils,
+};
 
-pub struct Init;
+#[derive(Default)]
+pub struct Init {
+    modules: Option<Vec<inventory::Module>>,
+}
 
 pub struct StorageReady {
     pub handle: storage::StorageHandle,
+    modules: Option<Vec<inventory::Module>>,
 }
 
 pub struct ModulesReady {
@@ -44,6 +57,8 @@ pub struct Executed {
 
 pub struct OryzaEngine<S> {
     config: Config,
+    // What the last run left behind, read before this run records its own phases
+    previous: state::RuntimeState,
     state: S,
 }
 
@@ -51,55 +66,186 @@ impl OryzaEngine<Init> {
     pub fn new(config: Config) -> Self {
         Self {
             config,
-            state: Init,
+            previous: state::RuntimeState::load().unwrap_or_default(),
+            state: Init::default(),
         }
     }
 
+    // Skips the inventory scan and mounts exactly these modules, in this order
+    #[allow(dead_code)]
+    pub fn with_modules(mut self, modules: Vec<inventory::Module>) -> Self {
+        self.state.modules = Some(modules);
+
+        self
+    }
+
     pub fn init_storage(
         self,
         mnt_base: &Path,
         img_path: &Path,
     ) -> Result<OryzaEngine<StorageReady>> {
-        let handle = storage::setup(
-            mnt_base,
-            img_path,
-            &self.config.moduledir,
-            matches!(
-                self.config.overlay_mode,
-                crate::conf::config::OverlayMode::Ext4
-            ),
-            matches!(
-                self.config.overlay_mode,
-                crate::conf::config::OverlayMode::Erofs
-            ),
-            &self.config.mountsource,
-            self.config.disable_umount,
-        )?;
+        if self.config.overlay_mode == crate::conf::config::OverlayMode::None {
+            log::warn!(
+                ">> Storage Backend: [NONE] Modules will be mounted from their source dirs."
+            );
+
+            state::record_stage(state::STAGE_STORAGE_READY);
+
+            return Ok(OryzaEngine {
+                config: self.config,
+                previous: self.previous,
+                state: StorageReady {
+                    handle: storage::StorageHandle {
+                        mount_point: mnt_base.to_path_buf(),
+                        mode: "none".to_string(),
+                        backing_image: None,
+                        fsck: None,
+                    },
+                    modules: self.state.modules,
+                },
+            });
+        }
+
+        let force_ext4 = match self.config.overlay_mode {
+            crate::conf::config::OverlayMode::Ext4 => true,
+            crate::conf::config::OverlayMode::Auto => {
+                let decision = storage::decide_auto_policy(img_path);
+
+                log::info!(">> Storage Policy [AUTO]: {}", decision.reason);
+
+                decision.use_ext4
+            }
+            _ => false,
+        };
+
+        let handle = utils::retry_mount("storage setup", &self.config.mount_retries, || {
+            storage::setup(
+                mnt_base,
+                img_path,
+                &self.config,
+                force_ext4,
+                matches!(
+                    self.config.overlay_mode,
+                    crate::conf::config::OverlayMode::Erofs
+                ),
+                &self.config.mountsource,
+                self.config.disable_umount,
+                self.config.fsck_every_boot,
+                self.config.tmpfs_size_mb,
+            )
+        })
+        .map_err(|e| MountError::Storage(format!("{:#}", e)))?;
 
         log::info!(">> Storage Backend: [{}]", handle.mode.to_uppercase());
 
+        state::RuntimeState::record_storage_ready(&handle.mode, &handle.mount_point);
+
+        state::record_stage(state::STAGE_STORAGE_READY);
+
         Ok(OryzaEngine {
             config: self.config,
-            state: StorageReady { handle },
+            previous: self.previous,
+            state: StorageReady {
+                handle,
+                modules: self.state.modules,
+            },
         })
     }
 }
 
 impl OryzaEngine<StorageReady> {
     pub fn scan_and_sync(mut self) -> Result<OryzaEngine<ModulesReady>> {
-        let modules = inventory::scan(&self.config.moduledir, &self.config)?;
+        // A partial module set says nothing about the rest, so their storage is left alone
+        let partial = self.state.modules.is_some()
+            || !self.config.only_modules.is_empty()
+            || !self.config.except_modules.is_empty();
+
+        let modules = match self.state.modules.take() {
+            Some(modules) => {
+                log::info!(
+                    ">> Inventory Scan: Skipped, using {} provided modules.",
+                    modules.len()
+                );
+
+                modules
+            }
+            None => {
+                let modules = inventory::scan(&self.config.moduledir, &self.config)?;
 
-        log::info!(
-            ">> Inventory Scan: Found {} enabled modules.",
-            modules.len()
-        );
+                log::info!(
+                    ">> Inventory Scan: Found {} enabled modules.",
+                    modules.len()
+                );
 
-        sync::perform_sync(&modules, &self.state.handle.mount_point)?;
+                modules
+            }
+        };
+
+        hooks::run(hooks::PRE_SYNC, self.config.hook_timeout_secs);
+
+        if self.state.handle.mode != "none" {
+            // Decided the same way mount_overlayfs will for layers on this storage
+            let storage = self.state.handle.mount_point.to_string_lossy().into_owned();
+
+            let userxattr = self
+                .config
+                .overlay_userxattr
+                .unwrap_or_else(|| overlay::needs_userxattr(&[storage]));
+
+            sync::perform_sync(
+                &modules,
+                &self.state.handle.mount_point,
+                self.config.preserve_sync_metadata,
+                userxattr,
+                !partial,
+            )?;
+
+            // Refresh the cached module stats while the trees are still in the page cache
+            sync::module_stats(&modules, &self.config.partitions);
+
+            if self.config.verify_sync != VerifySync::Off {
+                let mismatches = sync::verify_sync(
+                    &modules,
+                    &self.state.handle.mount_point,
+                    self.config.verify_sync == VerifySync::Content,
+                );
+
+                if !mismatches.is_empty() {
+                    let mut broken = HashSet::new();
+
+                    for m in &mismatches {
+                        log::error!(
+                            "[CRITICAL] Sync verification: {} ({}): {}",
+                            m.path.display(),
+                            m.module_id,
+                            m.reason
+                        );
+
+                        broken.insert(m.module_id.as_str());
+                    }
+
+                    // Drop the broken copies so the next boot syncs them from scratch
+                    for id in broken {
+                        let _ = std::fs::remove_dir_all(self.state.handle.mount_point.join(id));
+                    }
+
+                    bail!(
+                        "Sync verification failed: {} mismatched files",
+                        mismatches.len()
+                    );
+                }
+            }
+        }
 
         self.state.handle.commit(self.config.disable_umount)?;
 
+        state::RuntimeState::record_phase(state::PHASE_SYNCED);
+
+        hooks::run(hooks::POST_SYNC, self.config.hook_timeout_secs);
+
         Ok(OryzaEngine {
             config: self.config,
+            previous: self.previous,
             state: ModulesReady {
                 handle: self.state.handle,
                 modules,
@@ -118,8 +264,11 @@ impl OryzaEngine<ModulesReady> {
 
         plan.print_visuals();
 
+        state::RuntimeState::record_phase(state::PHASE_PLANNED);
+
         Ok(OryzaEngine {
             config: self.config,
+            previous: self.previous,
             state: Planned {
                 handle: self.state.handle,
                 modules: self.state.modules,
@@ -131,12 +280,29 @@ impl OryzaEngine<ModulesReady> {
 
 impl OryzaEngine<Planned> {
     pub fn execute(self) -> Result<OryzaEngine<Executed>> {
+        hooks::run(hooks::PRE_MOUNT, self.config.hook_timeout_secs);
+
         log::info!(">> Link Start! Executing mount plan...");
 
+        // Dropping the guard disarms the watchdog, including when execute bails
+        let watchdog = (self.config.mount_timeout_secs > 0).then(|| {
+            watchdog::Watchdog::arm(
+                "mount",
+                std::time::Duration::from_secs(self.config.mount_timeout_secs),
+            )
+        });
+
         let result = executor::execute(&self.state.plan, &self.config)?;
 
+        drop(watchdog);
+
+        state::record_stage(state::STAGE_MOUNTED);
+
+        hooks::run(hooks::POST_MOUNT, self.config.hook_timeout_secs);
+
         Ok(OryzaEngine {
             config: self.config,
+            previous: self.previous,
             state: Executed {
                 handle: self.state.handle,
                 modules: self.state.modules,
@@ -151,7 +317,13 @@ impl OryzaEngine<Executed> {
     pub fn finalize(self) -> Result<()> {
         let mut nuke_active = false;
 
-        if self.state.handle.mode == "ext4" && self.config.enable_nuke {
+        let nuke_requested = self.state.handle.mode == "ext4" && self.config.enable_nuke;
+
+        if nuke_requested && self.config.dry_run {
+            log::info!(">> Dry-run: Paw Pad Protocol skipped.");
+        } else if nuke_requested && !try_umount::is_driver_available() {
+            log::warn!("!! Paw Pad skipped: KSU driver is not available.");
+        } else if nuke_requested {
             log::info!(">> Engaging Paw Pad Protocol (Stealth)...");
 
             match try_umount::ksu_nuke_sysfs(
@@ -185,7 +357,33 @@ impl OryzaEngine<Executed> {
             .map(|op| op.partition_name.clone())
             .collect();
 
-        let state = state::RuntimeState::new(
+        let orphan_mounts = match executor::detect_orphan_mounts(
+            &self.state.plan,
+            &self.state.handle.mount_point,
+            !self.state.result.magic_module_ids.is_empty(),
+            &self.config,
+            &self.previous,
+        ) {
+            Ok(orphans) => orphans,
+            Err(e) => {
+                log::warn!("Orphan mount check failed: {:#}", e);
+
+                Vec::new()
+            }
+        };
+
+        for orphan in &orphan_mounts {
+            log::warn!("!! Orphan mount detected: {}", orphan.display());
+
+            if self.config.cleanup_orphan_mounts {
+                match rustix::mount::unmount(orphan, rustix::mount::UnmountFlags::DETACH) {
+                    Ok(_) => log::info!(">> Cleaned up orphan mount: {}", orphan.display()),
+                    Err(e) => log::warn!("Failed to unmount orphan {}: {}", orphan.display(), e),
+                }
+            }
+        }
+
+        let mut state = state::RuntimeState::new(
             self.state.handle.mode,
             self.state.handle.mount_point,
             self.state.result.overlay_module_ids,
@@ -193,14 +391,30 @@ impl OryzaEngine<Executed> {
             nuke_active,
             active_mounts,
             storage_stats,
+            self.state.result.writable_paths,
         );
 
+        state.orphan_mounts = orphan_mounts
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+
+        state.fsck = self.state.handle.fsck;
+
+        state.hook_failures = hooks::take_failures();
+
+        state.mount_reasons = self.state.result.mount_reasons;
+
         if let Err(e) = state.save() {
             log::error!("Failed to save runtime state: {:#}", e);
         }
 
         granary::disengage_ratoon_protocol();
 
+        progress::clear();
+
+        state::record_stage(state::STAGE_FINALIZED);
+
         log::info!(">> System operational. Mount sequence complete.");
 
         Ok(())
@@ -9,26 +9,32 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use extattr::lgetxattr;
-use serde::Serialize;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     conf::config::Config,
     core::{
         inventory::{self, MountMode},
         state::RuntimeState,
+        sync::{self, ModuleStats},
     },
     defs, utils,
 };
 
+const UPDATE_CHECK_TIMEOUT_SECS: u64 = 10;
+
 #[derive(Default)]
 struct ModuleProp {
     name: String,
     version: String,
     author: String,
     description: String,
+    version_code: i64,
+    update_json: String,
 }
 
 impl From<&Path> for ModuleProp {
@@ -36,15 +42,21 @@ impl From<&Path> for ModuleProp {
         let mut prop = ModuleProp::default();
 
         if let Ok(file) = fs::File::open(path) {
+            // lines() already drops CRLF endings. Keys follow utils::read_prop_line, and
+            // text values are kept whole since a `#` is legitimate in a description
             for line in BufReader::new(file).lines().map_while(Result::ok) {
-                if let Some((k, v)) = line.split_once('=') {
+                if let Some(k) = utils::prop_line_key(&line)
+                    && let Some((_, v)) = line.split_once('=')
+                {
                     let val = v.trim().to_string();
 
-                    match k.trim() {
+                    match k {
                         "name" => prop.name = val,
                         "version" => prop.version = val,
+                        "versionCode" => prop.version_code = parse_version_code(&val),
                         "author" => prop.author = val,
                         "description" => prop.description = val,
+                        "updateJson" => prop.update_json = val,
                         _ => {}
                     }
                 }
@@ -55,20 +67,40 @@ impl From<&Path> for ModuleProp {
     }
 }
 
+// Some packagers annotate the number with a trailing ` # comment`
+fn parse_version_code(val: &str) -> i64 {
+    val.split(" #")
+        .next()
+        .unwrap_or(val)
+        .trim()
+        .parse()
+        .unwrap_or(0)
+}
+
 #[derive(Serialize)]
-struct ModuleInfo {
+pub struct ModuleInfo {
     id: String,
     name: String,
     version: String,
+    version_code: i64,
     author: String,
     description: String,
+    update_json: String,
     mode: String,
+    mount_reason: String,
     is_mounted: bool,
     rules: inventory::ModuleRules,
+    file_count: u64,
+    total_size_bytes: u64,
 }
 
 impl ModuleInfo {
-    fn new(m: inventory::Module, mounted_set: &HashSet<&str>) -> Self {
+    fn new(
+        m: inventory::Module,
+        mounted_set: &HashSet<&str>,
+        mount_reason: String,
+        stats: ModuleStats,
+    ) -> Self {
         let prop = ModuleProp::from(m.source_path.join("module.prop").as_path());
 
         let mode_str = match m.rules.default_mode {
@@ -82,14 +114,65 @@ impl ModuleInfo {
             id: m.id,
             name: prop.name,
             version: prop.version,
+            version_code: prop.version_code,
             author: prop.author,
             description: prop.description,
+            update_json: prop.update_json,
             mode: mode_str.to_string(),
+            mount_reason,
             rules: m.rules,
+            file_count: stats.file_count,
+            total_size_bytes: stats.total_size_bytes,
         }
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateManifest {
+    version: String,
+    version_code: i64,
+    zip_url: String,
+    #[serde(default)]
+    changelog: String,
+}
+
+#[derive(Serialize)]
+struct UpdateInfo {
+    id: String,
+    name: String,
+    installed_version: String,
+    installed_version_code: i64,
+    latest_version: String,
+    latest_version_code: i64,
+    zip_url: String,
+    changelog: String,
+}
+
+impl UpdateInfo {
+    fn fetch(id: String, prop: ModuleProp) -> Result<Option<Self>> {
+        let body = utils::http_get(&prop.update_json, UPDATE_CHECK_TIMEOUT_SECS)?;
+
+        let manifest: UpdateManifest =
+            serde_json::from_slice(&body).context("Invalid update manifest")?;
+
+        if manifest.version_code <= prop.version_code {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            id,
+            name: prop.name,
+            installed_version: prop.version,
+            installed_version_code: prop.version_code,
+            latest_version: manifest.version,
+            latest_version_code: manifest.version_code,
+            zip_url: manifest.zip_url,
+            changelog: manifest.changelog,
+        }))
+    }
+}
+
 pub struct ModuleFile {
     pub relative_path: PathBuf,
     pub real_path: PathBuf,
@@ -141,7 +224,7 @@ impl ModuleFile {
     }
 }
 
-pub fn print_list(config: &Config) -> Result<()> {
+pub fn list(config: &Config) -> Result<Vec<ModuleInfo>> {
     let modules = inventory::scan(&config.moduledir, config)?;
 
     let state = RuntimeState::load().unwrap_or_default();
@@ -153,12 +236,84 @@ pub fn print_list(config: &Config) -> Result<()> {
         .map(|s| s.as_str())
         .collect();
 
-    let infos: Vec<ModuleInfo> = modules
+    let mut stats = sync::module_stats(&modules, &config.partitions);
+
+    Ok(modules
         .into_iter()
-        .map(|m| ModuleInfo::new(m, &mounted_ids))
+        .map(|m| {
+            let module_stats = stats.remove(&m.id).unwrap_or_default();
+
+            let mount_reason = state.mount_reasons.get(&m.id).cloned().unwrap_or_default();
+
+            ModuleInfo::new(m, &mounted_ids, mount_reason, module_stats)
+        })
+        .collect())
+}
+
+pub fn print_list(config: &Config) -> Result<()> {
+    println!("{}", serde_json::to_string(&list(config)?)?);
+
+    Ok(())
+}
+
+pub fn set_enabled(config: &Config, module_id: &str, enable: bool) -> Result<()> {
+    utils::validate_module_id(module_id)?;
+
+    if inventory::is_reserved_id(module_id, config) {
+        bail!("Refusing to toggle reserved module '{}'", module_id);
+    }
+
+    let module_dir = config.moduledir.join(module_id);
+
+    if !module_dir.is_dir() {
+        bail!(
+            "Module '{}' not found in {}",
+            module_id,
+            config.moduledir.display()
+        );
+    }
+
+    let marker = module_dir.join(defs::DISABLE_FILE_NAME);
+
+    if enable {
+        if marker.exists() {
+            fs::remove_file(&marker)
+                .with_context(|| format!("Failed to remove {}", marker.display()))?;
+        }
+    } else if !marker.exists() {
+        fs::File::create(&marker)
+            .with_context(|| format!("Failed to create {}", marker.display()))?;
+    }
+
+    Ok(())
+}
+
+pub fn check_updates(config: &Config) -> Result<()> {
+    let modules = inventory::scan(&config.moduledir, config)?;
+
+    let mut updates: Vec<UpdateInfo> = modules
+        .into_par_iter()
+        .filter_map(|m| {
+            let prop = ModuleProp::from(m.source_path.join("module.prop").as_path());
+
+            if prop.update_json.is_empty() {
+                return None;
+            }
+
+            match UpdateInfo::fetch(m.id.clone(), prop) {
+                Ok(info) => info,
+                Err(e) => {
+                    log::warn!("Update check failed for module '{}': {:#}", m.id, e);
+
+                    None
+                }
+            }
+        })
         .collect();
 
-    println!("{}", serde_json::to_string(&infos)?);
+    updates.sort_by(|a, b| a.id.cmp(&b.id));
+
+    println!("{}", serde_json::to_string(&updates)?);
 
     Ok(())
 }
@@ -178,12 +333,14 @@ pub fn update_description(
     let mode_str = match storage_mode {
         "tmpfs" => "Tmpfs",
         "erofs" => "EROFS",
+        "none" => "Direct",
         _ => "Ext4",
     };
 
     let status_emoji = match storage_mode {
         "tmpfs" => "🐾",
         "erofs" => "🚀",
+        "none" => "📂",
         _ => "💿",
     };
 
@@ -194,27 +351,64 @@ pub fn update_description(
     };
 
     let desc_text = format!(
-        "description=✅ Running～ ({}) {} | Overlay: {} | Magic: {}{}",
+        "✅ Running～ ({}) {} | Overlay: {} | Magic: {}{}",
         mode_str, status_emoji, overlay_count, magic_count, nuke_str
     );
 
-    let lines: Vec<String> = match fs::File::open(prop_path) {
-        Ok(file) => BufReader::new(file)
-            .lines()
-            .map_while(Result::ok)
-            .map(|line| {
-                if line.starts_with("description=") {
-                    desc_text.clone()
-                } else {
-                    line
-                }
-            })
-            .collect(),
-        Err(_) => return,
-    };
+    if let Err(e) = utils::update_prop_line(prop_path, "description", &desc_text) {
+        log::warn!("Failed to update module description: {:#}", e);
+    }
+}
+
+pub fn prop(config: &Config, module_id: &str, key: &str, value: Option<&str>) -> Result<()> {
+    utils::validate_module_id(module_id)?;
+
+    let prop_path = config.moduledir.join(module_id).join("module.prop");
+
+    if !prop_path.is_file() {
+        bail!(
+            "Module '{}' has no module.prop in {}",
+            module_id,
+            config.moduledir.display()
+        );
+    }
+
+    match value {
+        Some(value) => {
+            utils::update_prop_line(&prop_path, key, value)?;
+
+            println!("Set {} for module '{}'.", key, module_id);
+        }
+        None => match utils::read_prop_line(&prop_path, key)? {
+            Some(current) => println!("{}", current),
+            None => bail!("Key '{}' not found in module '{}'", key, module_id),
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::ScratchDir;
+
+    #[test]
+    fn module_prop_keeps_hash_in_values_and_skips_comment_lines() {
+        let dir = ScratchDir::new("modules-prop");
+        let path = dir.join("module.prop");
+
+        fs::write(
+            &path,
+            "# packaged by a tool\r\n#name=Old\r\nname=Demo\r\nversionCode=42 # bumped\r\ndescription=Best module #1 for audio\r\nupdateJson=https://example.com/u.json\r\n",
+        )
+        .unwrap();
+
+        let prop = ModuleProp::from(path.as_path());
 
-    let content = lines.join("\n");
-    if let Err(e) = utils::atomic_write(prop_path, format!("{}\n", content)) {
-        log::warn!("Failed to update module description: {}", e);
+        assert_eq!(prop.name, "Demo");
+        assert_eq!(prop.version_code, 42);
+        assert_eq!(prop.description, "Best module #1 for audio");
+        assert_eq!(prop.update_json, "https://example.com/u.json");
     }
 }
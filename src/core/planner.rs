@@ -2,20 +2,22 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
 };
 
 use anyhow::Result;
 use rayon::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
 use crate::{
     conf::config,
     core::inventory::{Module, MountMode},
     defs,
+    mount::node::{Node, NodeFileType},
 };
 
 #[derive(Debug, Clone)]
@@ -23,14 +25,36 @@ pub struct OverlayOperation {
     pub partition_name: String,
     pub target: String,
     pub lowerdirs: Vec<PathBuf>,
+    pub upperdir: Option<PathBuf>,
+    pub workdir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MissingDependency {
+    pub module: String,
+    pub requires: String,
+    pub installed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct DependencyReport {
+    pub cycle: Vec<String>,
+    pub missing: Vec<MissingDependency>,
 }
 
 #[derive(Debug, Default)]
 pub struct MountPlan {
+    pub module_order: Vec<String>,
+    pub dependencies: DependencyReport,
     pub overlay_ops: Vec<OverlayOperation>,
     pub magic_module_paths: Vec<PathBuf>,
+    pub magic_partitions: HashMap<PathBuf, Vec<String>>,
+    pub magic_exclusions: HashMap<PathBuf, HashSet<String>>,
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub ignored_module_ids: Vec<String>,
+    pub asset_only_modules: Vec<(String, Vec<&'static str>)>,
+    pub mount_reasons: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -40,18 +64,154 @@ pub struct ConflictEntry {
     pub contending_modules: Vec<String>,
 }
 
+// The same path is a directory in one module and a file or symlink in another, the merged
+// tree keeps only one side instead of combining them
+#[derive(Debug, Clone, Serialize)]
+pub struct TypeMismatch {
+    pub partition: String,
+    pub relative_path: String,
+    pub entries: Vec<(String, NodeFileType)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanSnapshot {
+    pub name: String,
+    pub timestamp: u64,
+    pub modules: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanModeChange {
+    pub module: String,
+    pub partition: String,
+    pub snapshot: Option<String>,
+    pub current: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanDiff {
+    pub snapshot: String,
+    pub added_modules: Vec<String>,
+    pub removed_modules: Vec<String>,
+    pub changes: Vec<PlanModeChange>,
+}
+
+impl PlanSnapshot {
+    pub fn diff(&self, current: &PlanSnapshot) -> PlanDiff {
+        let added_modules = current
+            .modules
+            .keys()
+            .filter(|id| !self.modules.contains_key(*id))
+            .cloned()
+            .collect();
+
+        let removed_modules = self
+            .modules
+            .keys()
+            .filter(|id| !current.modules.contains_key(*id))
+            .cloned()
+            .collect();
+
+        let empty = BTreeMap::new();
+
+        let mut ids: Vec<&String> = self.modules.keys().chain(current.modules.keys()).collect();
+
+        ids.sort();
+
+        ids.dedup();
+
+        let mut changes = Vec::new();
+
+        for id in ids {
+            let before = self.modules.get(id).unwrap_or(&empty);
+
+            let after = current.modules.get(id).unwrap_or(&empty);
+
+            let mut partitions: Vec<&String> = before.keys().chain(after.keys()).collect();
+
+            partitions.sort();
+
+            partitions.dedup();
+
+            for partition in partitions {
+                let snapshot = before.get(partition);
+
+                let current = after.get(partition);
+
+                if snapshot != current {
+                    changes.push(PlanModeChange {
+                        module: id.clone(),
+                        partition: partition.clone(),
+                        snapshot: snapshot.cloned(),
+                        current: current.cloned(),
+                    });
+                }
+            }
+        }
+
+        PlanDiff {
+            snapshot: self.name.clone(),
+            added_modules,
+            removed_modules,
+            changes,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ConflictReport {
     pub details: Vec<ConflictEntry>,
+    pub layered_modules: HashSet<String>,
+    pub uncontested_modules: HashSet<String>,
 }
 
 impl MountPlan {
+    pub fn module_rank(&self, module_root: &Path) -> usize {
+        module_root
+            .file_name()
+            .and_then(|name| self.module_order.iter().position(|id| name == id.as_str()))
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn snapshot(&self, name: &str, timestamp: u64) -> PlanSnapshot {
+        let mut modules: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
+        for op in &self.overlay_ops {
+            for layer in &op.lowerdirs {
+                if let Some(id) = crate::utils::extract_module_id(layer) {
+                    modules
+                        .entry(id)
+                        .or_default()
+                        .insert(op.partition_name.clone(), "overlay".to_string());
+                }
+            }
+        }
+
+        for (path, partitions) in &self.magic_partitions {
+            let Some(id) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+
+            let entry = modules.entry(id).or_default();
+
+            for partition in partitions {
+                entry.insert(partition.clone(), "magic".to_string());
+            }
+        }
+
+        PlanSnapshot {
+            name: name.to_string(),
+            timestamp,
+            modules,
+        }
+    }
+
     pub fn analyze_conflicts(&self) -> ConflictReport {
-        let mut conflicts: Vec<ConflictEntry> = self
+        let partials: Vec<ConflictReport> = self
             .overlay_ops
             .par_iter()
-            .flat_map(|op| {
-                let mut local_conflicts = Vec::new();
+            .map(|op| {
+                let mut local = ConflictReport::default();
 
                 let mut file_map: HashMap<String, Vec<String>> = HashMap::new();
 
@@ -73,29 +233,95 @@ impl MountPlan {
                 }
 
                 for (rel_path, modules) in file_map {
+                    local.layered_modules.extend(modules.iter().cloned());
+
                     if modules.len() > 1 {
-                        local_conflicts.push(ConflictEntry {
+                        local.details.push(ConflictEntry {
                             partition: op.partition_name.clone(),
                             relative_path: rel_path,
                             contending_modules: modules,
                         });
+                    } else {
+                        local.uncontested_modules.extend(modules);
                     }
                 }
 
-                local_conflicts
+                local
+            })
+            .collect();
+
+        let mut report = ConflictReport::default();
+
+        for partial in partials {
+            report.details.extend(partial.details);
+
+            report.layered_modules.extend(partial.layered_modules);
+
+            report
+                .uncontested_modules
+                .extend(partial.uncontested_modules);
+        }
+
+        report.details.sort_by(|a, b| {
+            a.partition
+                .cmp(&b.partition)
+                .then_with(|| a.relative_path.cmp(&b.relative_path))
+        });
+
+        report
+    }
+
+    pub fn analyze_type_mismatches(&self) -> Vec<TypeMismatch> {
+        let mut mismatches: Vec<TypeMismatch> = self
+            .overlay_ops
+            .par_iter()
+            .flat_map_iter(|op| {
+                let trees: Vec<(String, Node)> = op
+                    .lowerdirs
+                    .iter()
+                    .map(|layer_path| {
+                        let module_id = crate::utils::extract_module_id(layer_path)
+                            .unwrap_or_else(|| "UNKNOWN".into());
+
+                        let mut root = Node::new_root(op.partition_name.as_str());
+
+                        if let Err(e) = root.collect_module_files(layer_path) {
+                            log::debug!("Failed to walk {}: {:#}", layer_path.display(), e);
+                        }
+
+                        (module_id, root)
+                    })
+                    .collect();
+
+                let roots: Vec<(&str, &Node)> =
+                    trees.iter().map(|(id, node)| (id.as_str(), node)).collect();
+
+                let mut found = Vec::new();
+
+                find_type_mismatches(&op.partition_name, Path::new(""), &roots, &mut found);
+
+                found
             })
             .collect();
 
-        conflicts.sort_by(|a, b| {
+        mismatches.sort_by(|a, b| {
             a.partition
                 .cmp(&b.partition)
                 .then_with(|| a.relative_path.cmp(&b.relative_path))
         });
 
-        ConflictReport { details: conflicts }
+        mismatches
     }
 
     pub fn print_visuals(&self) {
+        if !self.ignored_module_ids.is_empty() {
+            log::info!(">> Ignored by rule: {}", self.ignored_module_ids.join(", "));
+        }
+
+        for (id, assets) in &self.asset_only_modules {
+            log::info!(">> No mount content, {} only: {}", assets.join(" + "), id);
+        }
+
         if self.overlay_ops.is_empty() && self.magic_module_paths.is_empty() {
             log::info!(">> Empty plan. Standby mode.");
 
@@ -146,16 +372,142 @@ impl MountPlan {
                     .map(|n| n.to_string_lossy())
                     .unwrap_or_else(|| "UNKNOWN".into());
 
-                log::info!("{} [Bind] {}", branch, mod_name);
+                let parts = self
+                    .magic_partitions
+                    .get(path)
+                    .map(|p| p.join(", "))
+                    .unwrap_or_default();
+
+                log::info!("{} [Bind] {} ({})", branch, mod_name, parts);
             }
         }
     }
 }
 
+fn find_type_mismatches(
+    partition: &str,
+    relative: &Path,
+    nodes: &[(&str, &Node)],
+    found: &mut Vec<TypeMismatch>,
+) {
+    let names: BTreeSet<&str> = nodes
+        .iter()
+        .flat_map(|(_, node)| node.children.keys().map(String::as_str))
+        .collect();
+
+    for name in names {
+        let entries: Vec<(&str, &Node)> = nodes
+            .iter()
+            .filter_map(|(id, node)| node.children.get(name).map(|child| (*id, child)))
+            .collect();
+
+        let path = relative.join(name);
+
+        let is_dir = |node: &Node| node.file_type == NodeFileType::Directory;
+
+        let has_dir = entries.iter().any(|(_, node)| is_dir(node));
+
+        // Whiteouts delete on purpose and never count as a clash
+        let has_other = entries
+            .iter()
+            .any(|(_, node)| !is_dir(node) && node.file_type != NodeFileType::Whiteout);
+
+        if has_dir && has_other {
+            found.push(TypeMismatch {
+                partition: partition.to_string(),
+                relative_path: path.to_string_lossy().to_string(),
+                entries: entries
+                    .iter()
+                    .map(|(id, node)| (id.to_string(), node.file_type))
+                    .collect(),
+            });
+
+            continue;
+        }
+
+        let dirs: Vec<(&str, &Node)> = entries.into_iter().filter(|(_, n)| is_dir(n)).collect();
+
+        if dirs.len() > 1 {
+            find_type_mismatches(partition, &path, &dirs, found);
+        }
+    }
+}
+
 struct ModuleContribution {
     id: String,
+    root: PathBuf,
     overlays: Vec<(String, PathBuf)>,
     magic_path: Option<PathBuf>,
+    magic_partitions: Vec<String>,
+    ignored_partitions: HashSet<String>,
+    reasons: Vec<String>,
+}
+
+// Kahn's algorithm, ties keep the scan order. Modules left over sit on or behind a cycle and
+// are appended unordered instead of failing the whole plan
+fn order_by_dependencies<'a>(
+    config: &config::Config,
+    modules: &'a [Module],
+) -> (Vec<&'a Module>, DependencyReport) {
+    let mut report = DependencyReport::default();
+
+    let index: HashMap<&str, usize> = modules
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.id.as_str(), i))
+        .collect();
+
+    let mut pending = vec![0usize; modules.len()];
+
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); modules.len()];
+
+    for (i, module) in modules.iter().enumerate() {
+        for dep in &module.requires {
+            match index.get(dep.as_str()) {
+                Some(&j) if j != i => {
+                    pending[i] += 1;
+
+                    dependents[j].push(i);
+                }
+                Some(_) => {}
+                None => report.missing.push(MissingDependency {
+                    module: module.id.clone(),
+                    requires: dep.clone(),
+                    installed: config.moduledir.join(dep).is_dir(),
+                }),
+            }
+        }
+    }
+
+    let mut ready: BTreeSet<usize> = (0..modules.len()).filter(|&i| pending[i] == 0).collect();
+
+    let mut placed = vec![false; modules.len()];
+
+    let mut ordered = Vec::with_capacity(modules.len());
+
+    while let Some(i) = ready.pop_first() {
+        placed[i] = true;
+
+        ordered.push(&modules[i]);
+
+        for &d in &dependents[i] {
+            pending[d] -= 1;
+
+            if pending[d] == 0 {
+                ready.insert(d);
+            }
+        }
+    }
+
+    for (i, module) in modules.iter().enumerate() {
+        if !placed[i] {
+            report.cycle.push(module.id.clone());
+
+            ordered.push(module);
+        }
+    }
+
+    (ordered, report)
 }
 
 pub fn generate(
@@ -165,17 +517,80 @@ pub fn generate(
 ) -> Result<MountPlan> {
     let mut plan = MountPlan::default();
 
+    let (modules, dependencies) = order_by_dependencies(config, modules);
+
+    if !dependencies.cycle.is_empty() {
+        log::warn!(
+            "Dependency cycle among modules: {}. Mounting them in scan order.",
+            dependencies.cycle.join(", ")
+        );
+    }
+
+    for missing in &dependencies.missing {
+        log::warn!(
+            "Module '{}' requires '{}', which is {}",
+            missing.module,
+            missing.requires,
+            if missing.installed {
+                "disabled"
+            } else {
+                "not installed"
+            }
+        );
+    }
+
+    plan.module_order = modules.iter().map(|m| m.id.clone()).collect();
+
+    plan.dependencies = dependencies;
+
     let mut target_partitions = defs::BUILTIN_PARTITIONS.to_vec();
 
     target_partitions.extend(config.partitions.iter().map(|s| s.as_str()));
 
+    for partition in &config.only_partitions {
+        if !target_partitions.contains(&partition.as_str()) {
+            log::warn!(
+                "Partition '{}' given to --mount-only-partitions is not a known partition",
+                partition
+            );
+        }
+    }
+
+    // Partitions left out by --mount-only-partitions, kept away from magic mount as well
+    let mut skipped_partitions: HashSet<String> = HashSet::new();
+
+    if !config.only_partitions.is_empty() {
+        target_partitions.retain(|p| {
+            let keep = config.only_partitions.iter().any(|o| o == p);
+
+            if !keep {
+                skipped_partitions.insert(p.to_string());
+            }
+
+            keep
+        });
+
+        log::warn!(
+            ">> Mounting only partitions: {}",
+            if target_partitions.is_empty() {
+                "none".to_string()
+            } else {
+                target_partitions.join(", ")
+            }
+        );
+    }
+
     let contributions: Vec<Option<ModuleContribution>> = modules
         .par_iter()
         .map(|module| {
             let mut content_path = storage_root.join(&module.id);
 
+            let mut unsynced = false;
+
             if !content_path.exists() {
                 content_path = module.source_path.clone();
+
+                unsynced = true;
             }
 
             if !content_path.exists() {
@@ -185,7 +600,11 @@ pub fn generate(
             let mut contrib = ModuleContribution {
                 id: module.id.clone(),
                 overlays: Vec::new(),
+                root: content_path.clone(),
                 magic_path: None,
+                magic_partitions: Vec::new(),
+                ignored_partitions: HashSet::new(),
+                reasons: Vec::new(),
             };
 
             let mut has_any_action = false;
@@ -208,7 +627,33 @@ pub fn generate(
                         continue;
                     }
 
-                    let mode = module.rules.get_mode(&dir_name);
+                    let partition_default = config.partition_modes.get(&dir_name);
+
+                    let mut mode = module
+                        .rules
+                        .get_partition_mode(&dir_name, partition_default);
+
+                    let mut reason = module
+                        .rules
+                        .partition_mode_reason(&dir_name, partition_default);
+
+                    // Only the sync step turns `.replace` sentinels into overlay opacity, an
+                    // unsynced layer would expose them as files, magic mount honors them as-is
+                    if mode == MountMode::Overlay && unsynced && has_replace_marker(&path) {
+                        log::warn!(
+                            "{}/{} has replace markers but no synced copy, routing partition to magic",
+                            module.id,
+                            dir_name
+                        );
+
+                        mode = MountMode::Magic;
+
+                        reason = "fallback:unsynced-replace-marker".to_string();
+                    }
+
+                    if !contrib.reasons.contains(&reason) {
+                        contrib.reasons.push(reason);
+                    }
 
                     match mode {
                         MountMode::Overlay => {
@@ -217,32 +662,92 @@ pub fn generate(
                             has_any_action = true;
                         }
                         MountMode::Magic => {
+                            if module
+                                .rules
+                                .base_partition_mode(&dir_name, partition_default)
+                                == MountMode::Overlay
+                            {
+                                log::debug!(
+                                    "{}/{} has magic subtree rules, routing partition to magic",
+                                    module.id,
+                                    dir_name
+                                );
+                            }
+
                             contrib.magic_path = Some(content_path.clone());
 
+                            contrib.magic_partitions.push(dir_name);
+
                             has_any_action = true;
                         }
                         MountMode::Ignore => {
                             log::debug!("Ignoring {}/{} per rule", module.id, dir_name);
+
+                            contrib.ignored_partitions.insert(dir_name);
                         }
                     }
                 }
             }
 
-            if has_any_action { Some(contrib) } else { None }
+            if has_any_action || !contrib.ignored_partitions.is_empty() {
+                Some(contrib)
+            } else {
+                None
+            }
         })
         .collect();
 
+    plan.asset_only_modules = modules
+        .iter()
+        .zip(&contributions)
+        .filter(|(module, contrib)| contrib.is_none() && !module.non_mount_assets.is_empty())
+        .map(|(module, _)| (module.id.clone(), module.non_mount_assets.clone()))
+        .collect();
+
     let mut overlay_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
 
-    let mut magic_paths = HashSet::new();
+    let mut magic_paths = Vec::new();
+
+    let mut magic_partitions: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+    let mut magic_exclusions: HashMap<PathBuf, HashSet<String>> = HashMap::new();
 
     let mut overlay_ids = HashSet::new();
 
     let mut magic_ids = HashSet::new();
 
+    let mut ignored_ids = Vec::new();
+
     for contrib in contributions.into_iter().flatten() {
+        plan.mount_reasons
+            .insert(contrib.id.clone(), contrib.reasons.join(", "));
+
+        if contrib.overlays.is_empty() && contrib.magic_path.is_none() {
+            ignored_ids.push(contrib.id.clone());
+        }
+
+        // Magic mount walks the whole module, including after an overlay fallback, so
+        // ignored partitions have to be excluded there explicitly
+        if !contrib.ignored_partitions.is_empty() || !skipped_partitions.is_empty() {
+            magic_exclusions
+                .entry(contrib.root.clone())
+                .or_default()
+                .extend(
+                    contrib
+                        .ignored_partitions
+                        .into_iter()
+                        .chain(skipped_partitions.iter().cloned()),
+                );
+        }
+
         if let Some(path) = contrib.magic_path {
-            magic_paths.insert(path);
+            let mut parts = contrib.magic_partitions;
+
+            parts.sort();
+
+            magic_partitions.insert(path.clone(), parts);
+
+            magic_paths.push(path);
 
             magic_ids.insert(contrib.id.clone());
         }
@@ -255,43 +760,33 @@ pub fn generate(
     }
 
     for (part, layers) in overlay_groups {
-        let initial_target_path = format!("/{}", part);
-
-        let target_path_obj = Path::new(&initial_target_path);
-
-        if fs::symlink_metadata(target_path_obj)
-            .map(|m| m.file_type().is_symlink())
-            .unwrap_or(false)
-        {
-            log::warn!(
-                "Skipping overlay on symlink partition: {}",
-                initial_target_path
-            );
-
+        let Some(resolved_target) = resolve_partition_target(&part) else {
             continue;
-        }
+        };
 
-        let resolved_target = if target_path_obj.exists() {
-            match target_path_obj.canonicalize() {
-                Ok(p) => p,
-                Err(_) => continue,
-            }
+        // upper and work must share a filesystem, so both live under the same storage dir
+        let (upperdir, workdir) = if config.overlay_writable {
+            let rw = storage_root.join(defs::STORAGE_RW_DIR_NAME).join(&part);
+
+            (Some(rw.join("upperdir")), Some(rw.join("workdir")))
         } else {
-            continue;
+            (None, None)
         };
 
-        if !resolved_target.is_dir() {
-            continue;
-        }
-
         plan.overlay_ops.push(OverlayOperation {
             partition_name: part,
             target: resolved_target.to_string_lossy().to_string(),
             lowerdirs: layers,
+            upperdir,
+            workdir,
         });
     }
 
-    plan.magic_module_paths = magic_paths.into_iter().collect();
+    plan.magic_module_paths = magic_paths;
+
+    plan.magic_partitions = magic_partitions;
+
+    plan.magic_exclusions = magic_exclusions;
 
     plan.overlay_module_ids = overlay_ids.into_iter().collect();
 
@@ -301,9 +796,44 @@ pub fn generate(
 
     plan.magic_module_ids.sort();
 
+    ignored_ids.sort();
+
+    plan.ignored_module_ids = ignored_ids;
+
     Ok(plan)
 }
 
+static PARTITION_TARGETS: LazyLock<Mutex<HashMap<String, Option<PathBuf>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Partition mount points do not move while the process runs, so each is resolved once
+fn resolve_partition_target(part: &str) -> Option<PathBuf> {
+    let mut cache = PARTITION_TARGETS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    cache
+        .entry(part.to_string())
+        .or_insert_with(|| {
+            let target = Path::new("/").join(part);
+
+            if fs::symlink_metadata(&target)
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false)
+            {
+                log::warn!(
+                    "Skipping overlay on symlink partition: {}",
+                    target.display()
+                );
+
+                return None;
+            }
+
+            target.canonicalize().ok().filter(|p| p.is_dir())
+        })
+        .clone()
+}
+
 fn has_files(path: &Path) -> bool {
     if let Ok(entries) = fs::read_dir(path)
         && entries.flatten().next().is_some()
@@ -313,3 +843,10 @@ fn has_files(path: &Path) -> bool {
 
     false
 }
+
+fn has_replace_marker(path: &Path) -> bool {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .any(|e| e.file_type().is_file() && e.file_name() == defs::REPLACE_DIR_FILE_NAME)
+}
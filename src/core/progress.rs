@@ -0,0 +1,64 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{defs, utils};
+
+pub const PHASE_SYNC: &str = "sync";
+pub const PHASE_OVERLAY: &str = "overlay";
+pub const PHASE_MAGIC: &str = "magic";
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Progress {
+    pub phase: String,
+    pub module: Option<String>,
+    pub target: Option<String>,
+    pub done: usize,
+    pub total: usize,
+    pub timestamp: u64,
+}
+
+pub fn report(phase: &str, module: Option<&str>, target: Option<&str>, done: usize, total: usize) {
+    let progress = Progress {
+        phase: phase.to_string(),
+        module: module.map(str::to_string),
+        target: target.map(str::to_string),
+        done,
+        total,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+
+    // Progress is best effort, the WebUI simply keeps showing the last value it read
+    match serde_json::to_string(&progress) {
+        Ok(json) => {
+            if let Err(e) = utils::atomic_write(defs::PROGRESS_FILE, json) {
+                log::debug!("Failed to write progress: {:#}", e);
+            }
+        }
+        Err(e) => log::debug!("Failed to serialize progress: {}", e),
+    }
+}
+
+pub fn current() -> Option<Progress> {
+    let content = fs::read_to_string(defs::PROGRESS_FILE).ok()?;
+
+    serde_json::from_str(&content).ok()
+}
+
+pub fn clear() {
+    if Path::new(defs::PROGRESS_FILE).exists()
+        && let Err(e) = fs::remove_file(defs::PROGRESS_FILE)
+    {
+        log::warn!("Failed to remove progress file: {}", e);
+    }
+}
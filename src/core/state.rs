@@ -2,15 +2,43 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::HashMap,
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::defs;
+use crate::{core::storage::FsckReport, defs, utils};
+
+pub const PHASE_STORAGE_READY: &str = "storage-ready";
+pub const PHASE_SYNCED: &str = "synced";
+pub const PHASE_PLANNED: &str = "planned";
+pub const PHASE_OVERLAY_DONE: &str = "overlay-done";
+pub const PHASE_MAGIC_DONE: &str = "magic-done";
+pub const PHASE_FINALIZED: &str = "finalized";
+pub const PHASE_WATCHDOG_TIMEOUT: &str = "watchdog-timeout";
+
+pub const STAGE_INIT: &str = "init";
+pub const STAGE_STORAGE_READY: &str = "storage-ready";
+pub const STAGE_MOUNTED: &str = "mounted";
+pub const STAGE_FAILED: &str = "failed";
+pub const STAGE_FINALIZED: &str = "finalized";
+
+// A single `<stage> <unix timestamp>` line for companion scripts, `read stage ts < stage`
+// is all it takes to follow the daemon without parsing JSON or logs
+pub fn record_stage(stage: &str) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if let Err(e) = utils::atomic_write(defs::STAGE_FILE, format!("{} {}\n", stage, timestamp)) {
+        log::warn!("Failed to record stage '{}': {:#}", stage, e);
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct RuntimeState {
@@ -31,6 +59,18 @@ pub struct RuntimeState {
     pub storage_percent: u8,
     #[serde(default)]
     pub zygisksu_enforce: bool,
+    #[serde(default)]
+    pub writable_paths: Vec<String>,
+    #[serde(default)]
+    pub orphan_mounts: Vec<String>,
+    #[serde(default)]
+    pub phase: String,
+    #[serde(default)]
+    pub fsck: Option<FsckReport>,
+    #[serde(default)]
+    pub hook_failures: Vec<String>,
+    #[serde(default)]
+    pub mount_reasons: HashMap<String, String>,
 }
 
 impl RuntimeState {
@@ -43,6 +83,7 @@ impl RuntimeState {
         nuke_active: bool,
         active_mounts: Vec<String>,
         storage_info: (u64, u64, u8),
+        writable_paths: Vec<String>,
     ) -> Self {
         let start = SystemTime::now();
 
@@ -53,7 +94,7 @@ impl RuntimeState {
 
         let pid = std::process::id();
 
-        let zygisksu_enforce = crate::utils::check_zygisksu_enforce_status();
+        let zygisksu_enforce = crate::try_umount::zygisksu_enforce();
 
         Self {
             timestamp,
@@ -68,19 +109,64 @@ impl RuntimeState {
             storage_used: storage_info.1,
             storage_percent: storage_info.2,
             zygisksu_enforce,
+            writable_paths,
+            orphan_mounts: Vec::new(),
+            phase: PHASE_FINALIZED.to_string(),
+            fsck: None,
+            hook_failures: Vec::new(),
+            mount_reasons: HashMap::new(),
         }
     }
 
     pub fn save(&self) -> Result<()> {
         let json = serde_json::to_string_pretty(self)?;
 
-        fs::write(defs::STATE_FILE, json)?;
+        utils::atomic_write(defs::STATE_FILE, json)?;
 
         Ok(())
     }
 
+    pub fn record_phase(phase: &str) {
+        Self::record(phase, |_| {});
+    }
+
+    // Later phases of the same run keep these, so a run that dies after this point still
+    // tells which storage it mounted
+    pub fn record_storage_ready(storage_mode: &str, mount_point: &Path) {
+        Self::record(PHASE_STORAGE_READY, |state| {
+            state.storage_mode = storage_mode.to_string();
+
+            state.mount_point = mount_point.to_path_buf();
+        });
+    }
+
+    fn record(phase: &str, update: impl FnOnce(&mut Self)) {
+        let pid = std::process::id();
+
+        // A state left by another process belongs to a previous run, start a fresh breadcrumb
+        let mut state = Self::load()
+            .ok()
+            .filter(|s| s.pid == pid)
+            .unwrap_or_default();
+
+        state.pid = pid;
+
+        state.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        state.phase = phase.to_string();
+
+        update(&mut state);
+
+        if let Err(e) = state.save() {
+            log::warn!("Failed to record phase '{}': {:#}", phase, e);
+        }
+    }
+
     pub fn load() -> Result<Self> {
-        if !std::path::Path::new(defs::STATE_FILE).exists() {
+        if !Path::new(defs::STATE_FILE).exists() {
             return Ok(Self::default());
         }
 
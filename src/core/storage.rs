@@ -11,18 +11,112 @@ use rustix::{
     fs::Mode,
     mount::{UnmountFlags, unmount},
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::try_umount::send_unmountable;
-use crate::{core::state::RuntimeState, utils};
+use crate::{
+    conf::config::Config,
+    core::{
+        inventory::{self, MountMode},
+        state::RuntimeState,
+        sync,
+    },
+    utils,
+};
 
 const DEFAULT_SELINUX_CONTEXT: &str = "u:object_r:system_file:s0";
 
+// Below this much available memory a tmpfs holding every module risks OOM kills during boot
+const AUTO_TMPFS_MIN_AVAILABLE_MB: u64 = 1024;
+
+// Synced modules may take at most this share of MemAvailable before tmpfs is refused
+const TMPFS_MAX_MEM_DIVISOR: u64 = 2;
+
+pub struct AutoPolicyDecision {
+    pub use_ext4: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FsckReport {
+    pub exit_code: i32,
+    pub fixes: usize,
+    pub data_lost: bool,
+    pub summary: String,
+    pub scheduled: bool,
+}
+
+impl FsckReport {
+    fn parse(exit_code: i32, output: &str, scheduled: bool) -> Self {
+        // Every repair e2fsck -y applies is echoed as a "<question>? yes" answer
+        let fixes = output
+            .lines()
+            .filter(|l| l.trim_end().ends_with("? yes"))
+            .count();
+
+        // Cleared or orphaned inodes mean file contents were dropped or moved to lost+found
+        let data_lost = output.lines().any(|l| {
+            l.starts_with("Clear")
+                || l.contains("Connect to /lost+found")
+                || l.contains("deleted/unused inode")
+        });
+
+        let summary = output
+            .lines()
+            .rev()
+            .find(|l| l.contains(" files ") && l.contains(" blocks"))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+
+        Self {
+            exit_code,
+            fixes,
+            data_lost,
+            summary,
+            scheduled,
+        }
+    }
+
+    pub fn repaired(&self) -> bool {
+        self.exit_code != 0
+    }
+
+    pub fn describe(&self) -> String {
+        let trigger = if self.scheduled {
+            "fsck_every_boot"
+        } else {
+            "mount failure"
+        };
+
+        let outcome = if !self.repaired() {
+            "clean, nothing to fix".to_string()
+        } else if self.data_lost {
+            format!(
+                "{} fixes, some module files were lost or moved to lost+found",
+                self.fixes
+            )
+        } else {
+            format!("{} fixes, no data lost", self.fixes)
+        };
+
+        if self.summary.is_empty() {
+            format!("modules.img checked after {}: {}", trigger, outcome)
+        } else {
+            format!(
+                "modules.img checked after {}: {} ({})",
+                trigger, outcome, self.summary
+            )
+        }
+    }
+}
+
 pub struct StorageHandle {
     pub mount_point: PathBuf,
     pub mode: String,
     pub backing_image: Option<PathBuf>,
+    pub fsck: Option<FsckReport>,
 }
 
 impl StorageHandle {
@@ -55,7 +149,7 @@ impl StorageHandle {
 }
 
 #[derive(Serialize)]
-struct StorageStatus {
+pub struct StorageStatus {
     #[serde(rename = "type")]
     mode: String,
     mount_point: String,
@@ -84,14 +178,17 @@ pub fn get_usage(path: &Path) -> (u64, u64, u8) {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn setup(
     mnt_base: &Path,
     img_path: &Path,
-    _moduledir: &Path,
+    config: &Config,
     force_ext4: bool,
     use_erofs: bool,
     mount_source: &str,
     disable_umount: bool,
+    fsck_every_boot: bool,
+    tmpfs_size_mb: u64,
 ) -> Result<StorageHandle> {
     if utils::is_mounted(mnt_base) {
         let _ = unmount(mnt_base, UnmountFlags::DETACH);
@@ -110,7 +207,7 @@ pub fn setup(
     if use_erofs && utils::is_erofs_supported() {
         let erofs_path = img_path.with_extension("erofs");
 
-        utils::mount_tmpfs(mnt_base, mount_source)?;
+        utils::mount_tmpfs_sized(mnt_base, mount_source, tmpfs_size_mb)?;
 
         try_hide(mnt_base);
 
@@ -118,10 +215,14 @@ pub fn setup(
             mount_point: mnt_base.to_path_buf(),
             mode: "erofs_staging".to_string(),
             backing_image: Some(erofs_path),
+            fsck: None,
         });
     }
 
-    if !force_ext4 && try_setup_tmpfs(mnt_base, mount_source)? {
+    if !force_ext4
+        && tmpfs_fits(config, img_path, tmpfs_size_mb)
+        && try_setup_tmpfs(mnt_base, mount_source, tmpfs_size_mb)?
+    {
         try_hide(mnt_base);
 
         let erofs_path = img_path.with_extension("erofs");
@@ -134,18 +235,144 @@ pub fn setup(
             mount_point: mnt_base.to_path_buf(),
             mode: "tmpfs".to_string(),
             backing_image: None,
+            fsck: None,
         });
     }
 
-    let handle = setup_ext4_image(mnt_base, img_path)?;
+    let handle = setup_ext4_image(mnt_base, img_path, fsck_every_boot)?;
 
     try_hide(mnt_base);
 
     Ok(handle)
 }
 
-fn try_setup_tmpfs(target: &Path, mount_source: &str) -> Result<bool> {
-    if utils::mount_tmpfs(target, mount_source).is_ok() {
+fn mem_available_mb() -> Option<u64> {
+    fs::read_to_string("/proc/meminfo")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|rest| {
+            rest.trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kb| kb / 1024)
+}
+
+pub fn decide_auto_policy(img_path: &Path) -> AutoPolicyDecision {
+    let Some(available) = mem_available_mb() else {
+        return AutoPolicyDecision {
+            use_ext4: false,
+            reason: "MemAvailable unknown, using tmpfs".to_string(),
+        };
+    };
+
+    if available >= AUTO_TMPFS_MIN_AVAILABLE_MB {
+        return AutoPolicyDecision {
+            use_ext4: false,
+            reason: format!(
+                "{} MB available (>= {} MB), using tmpfs",
+                available, AUTO_TMPFS_MIN_AVAILABLE_MB
+            ),
+        };
+    }
+
+    if !img_path.exists() {
+        return AutoPolicyDecision {
+            use_ext4: false,
+            reason: format!(
+                "{} MB available (< {} MB) but {} is missing, using tmpfs",
+                available,
+                AUTO_TMPFS_MIN_AVAILABLE_MB,
+                img_path.display()
+            ),
+        };
+    }
+
+    AutoPolicyDecision {
+        use_ext4: true,
+        reason: format!(
+            "{} MB available (< {} MB), using ext4 to avoid OOM",
+            available, AUTO_TMPFS_MIN_AVAILABLE_MB
+        ),
+    }
+}
+
+// Refuses tmpfs when the modules would not fit the size cap or would eat too much of the
+// available memory, as long as there is an ext4 image to fall back to
+fn tmpfs_fits(config: &Config, img_path: &Path, tmpfs_size_mb: u64) -> bool {
+    let needed_mb = estimate_modules_mb(config);
+
+    let available = mem_available_mb();
+
+    let limit_mb = match (available.map(|a| a / TMPFS_MAX_MEM_DIVISOR), tmpfs_size_mb) {
+        (Some(mem), 0) => Some(mem),
+        (Some(mem), cap) => Some(mem.min(cap)),
+        (None, 0) => None,
+        (None, cap) => Some(cap),
+    };
+
+    let fits = limit_mb.is_none_or(|limit| needed_mb <= limit);
+
+    log::info!(
+        ">> Tmpfs sizing: modules ~{} MB, MemAvailable {}, size cap {}, limit {}",
+        needed_mb,
+        available.map_or("unknown".to_string(), |a| format!("{} MB", a)),
+        if tmpfs_size_mb > 0 {
+            format!("{} MB", tmpfs_size_mb)
+        } else {
+            "kernel default".to_string()
+        },
+        limit_mb.map_or("none".to_string(), |l| format!("{} MB", l)),
+    );
+
+    if fits {
+        return true;
+    }
+
+    if !img_path.exists() {
+        log::warn!(
+            "!! Modules (~{} MB) exceed the tmpfs limit but {} is missing, using tmpfs anyway",
+            needed_mb,
+            img_path.display()
+        );
+
+        return true;
+    }
+
+    log::warn!(
+        "!! Modules (~{} MB) exceed the tmpfs limit, falling back to ext4",
+        needed_mb
+    );
+
+    false
+}
+
+// Counts what sync will copy: the partition trees of the modules the scan keeps, minus the
+// Magic ones that mount from their source. The per-module sizes come from the sync manifest
+fn estimate_modules_mb(config: &Config) -> u64 {
+    let Ok(report) = inventory::scan_report(&config.moduledir, config) else {
+        return 0;
+    };
+
+    let modules: Vec<_> = report
+        .modules
+        .into_iter()
+        .filter(|m| m.rules.default_mode != MountMode::Magic)
+        .collect();
+
+    let bytes: u64 = sync::module_stats(&modules, &config.partitions)
+        .values()
+        .map(|stats| stats.total_size_bytes)
+        .sum();
+
+    bytes.div_ceil(1024 * 1024)
+}
+
+fn try_setup_tmpfs(target: &Path, mount_source: &str, size_mb: u64) -> Result<bool> {
+    if utils::mount_tmpfs_sized(target, mount_source, size_mb).is_ok() {
         if utils::is_overlay_xattr_supported(target).is_ok() {
             return Ok(true);
         } else {
@@ -156,7 +383,11 @@ fn try_setup_tmpfs(target: &Path, mount_source: &str) -> Result<bool> {
     Ok(false)
 }
 
-fn setup_ext4_image(target: &Path, img_path: &Path) -> Result<StorageHandle> {
+fn setup_ext4_image(
+    target: &Path,
+    img_path: &Path,
+    fsck_every_boot: bool,
+) -> Result<StorageHandle> {
     if !img_path.exists() {
         bail!(
             "Modules image not found at {} and automatic creation (mkfs.ext4) has been disabled.",
@@ -164,22 +395,49 @@ fn setup_ext4_image(target: &Path, img_path: &Path) -> Result<StorageHandle> {
         );
     }
 
-    if utils::mount_image(img_path, target).is_err() {
-        if utils::repair_image(img_path).is_ok() {
-            utils::mount_image(img_path, target)
-                .context("Failed to mount modules.img after repair")?;
-        } else {
-            bail!("Failed to repair modules.img");
+    let mut fsck = None;
+
+    if fsck_every_boot {
+        match check_image(img_path, true) {
+            Ok(report) => fsck = Some(report),
+            Err(e) => log::warn!("Scheduled fsck of modules.img failed: {:#}", e),
         }
     }
 
+    if utils::mount_image(img_path, target).is_err() {
+        let report = check_image(img_path, false).context("Failed to repair modules.img")?;
+
+        fsck = Some(report);
+
+        utils::mount_image(img_path, target).context("Failed to mount modules.img after repair")?;
+    }
+
     Ok(StorageHandle {
         mount_point: target.to_path_buf(),
         mode: "ext4".to_string(),
         backing_image: Some(img_path.to_path_buf()),
+        fsck,
     })
 }
 
+fn check_image(img_path: &Path, scheduled: bool) -> Result<FsckReport> {
+    let (code, output) = utils::repair_image(img_path)?;
+
+    for line in output.lines().filter(|l| !l.trim().is_empty()) {
+        log::debug!("e2fsck: {}", line);
+    }
+
+    let report = FsckReport::parse(code, &output, scheduled);
+
+    if report.data_lost {
+        log::warn!("!! {}", report.describe());
+    } else {
+        log::info!(">> {}", report.describe());
+    }
+
+    Ok(report)
+}
+
 #[allow(dead_code)]
 pub fn finalize_storage_permissions(target: &Path) {
     if let Err(e) = rustix::fs::chmod(target, Mode::from(0o755)) {
@@ -199,7 +457,7 @@ pub fn finalize_storage_permissions(target: &Path) {
     }
 }
 
-pub fn print_status() -> Result<()> {
+pub fn collect_status() -> StorageStatus {
     let state = RuntimeState::load().ok();
     let fallback_mnt = crate::conf::config::Config::load_default()
         .map(|c| c.hybrid_mnt_dir)
@@ -238,15 +496,17 @@ pub fn print_status() -> Result<()> {
         }
     }
 
-    let status = StorageStatus {
+    StorageStatus {
         mode,
         mount_point: mnt_base.to_string_lossy().to_string(),
         usage_percent: percent,
         total_size: total,
         used_size: used,
-    };
+    }
+}
 
-    println!("{}", serde_json::to_string(&status)?);
+pub fn print_status() -> Result<()> {
+    println!("{}", serde_json::to_string(&collect_status())?);
 
     Ok(())
 }
@@ -1,54 +1,113 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{collections::HashSet, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{BufReader, Read},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 use anyhow::Result;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
 use crate::{
-    core::inventory::{Module, MountMode},
+    core::{
+        inventory::{Module, MountMode},
+        progress,
+    },
     defs, utils,
 };
 
-pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
+// Bounds the stats walk so one pathological module cannot stall the module listing
+const STATS_MAX_ENTRIES: u64 = 200_000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleStats {
+    pub file_count: u64,
+    pub total_size_bytes: u64,
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    prop_stamp: String,
+    #[serde(flatten)]
+    stats: ModuleStats,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncManifest {
+    #[serde(default)]
+    modules: BTreeMap<String, ManifestEntry>,
+}
+
+pub fn perform_sync(
+    modules: &[Module],
+    target_base: &Path,
+    preserve_metadata: bool,
+    userxattr: bool,
+    prune: bool,
+) -> Result<()> {
     log::info!("Starting smart module sync to {}", target_base.display());
 
-    prune_orphaned_modules(modules, target_base)?;
+    if prune {
+        prune_orphaned_modules(modules, target_base)?;
+    } else {
+        log::info!("Partial module set, keeping the storage of unlisted modules");
+    }
+
+    let total = modules.len();
+
+    // Held while writing so parallel workers never publish the count out of order
+    let done = Mutex::new(0usize);
+
+    progress::report(progress::PHASE_SYNC, None, None, 0, total);
 
     modules.par_iter().for_each(|module| {
-        if matches!(module.rules.default_mode, MountMode::Magic) {
-            log::debug!("Skipping sync for Magic Mount module: {}", module.id);
+        sync_module(module, target_base, preserve_metadata, userxattr);
+
+        if let Ok(mut done) = done.lock() {
+            *done += 1;
 
-            return;
+            progress::report(progress::PHASE_SYNC, Some(&module.id), None, *done, total);
         }
+    });
 
-        let dst = target_base.join(&module.id);
+    Ok(())
+}
 
-        let has_content = defs::BUILTIN_PARTITIONS.iter().any(|p| {
-            let part_path = module.source_path.join(p);
+fn sync_module(module: &Module, target_base: &Path, preserve_metadata: bool, userxattr: bool) {
+    if matches!(module.rules.default_mode, MountMode::Magic) {
+        log::debug!("Skipping sync for Magic Mount module: {}", module.id);
 
-            part_path.exists() && has_files_recursive(&part_path)
-        });
+        return;
+    }
 
-        if has_content && should_sync(&module.source_path, &dst) {
-            log::info!("Syncing module: {} (Updated/New)", module.id);
+    let dst = target_base.join(&module.id);
 
-            if dst.exists()
-                && let Err(e) = fs::remove_dir_all(&dst)
-            {
-                log::warn!("Failed to clean target dir for {}: {}", module.id, e);
-            }
+    if has_partition_content(module) && should_sync(&module.source_path, &dst) {
+        log::info!("Syncing module: {} (Updated/New)", module.id);
 
-            if let Err(e) = utils::sync_dir(&module.source_path, &dst, true) {
-                log::error!("Failed to sync module {}: {}", module.id, e);
-            }
-        } else {
-            log::debug!("Skipping module: {}", module.id);
+        if dst.exists()
+            && let Err(e) = fs::remove_dir_all(&dst)
+        {
+            log::warn!("Failed to clean target dir for {}: {}", module.id, e);
         }
-    });
 
-    Ok(())
+        if let Err(e) = utils::sync_dir(&module.source_path, &dst, true, preserve_metadata) {
+            log::error!("Failed to sync module {}: {}", module.id, e);
+        } else {
+            apply_replace_markers(&module.id, &dst, userxattr);
+        }
+    } else {
+        log::debug!("Skipping module: {}", module.id);
+    }
 }
 
 fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()> {
@@ -67,7 +126,11 @@ fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()>
 
         let name = name_os.to_string_lossy();
 
-        if name != "lost+found" && name != "meta-hybrid" && !active_ids.contains(name.as_ref()) {
+        if name != "lost+found"
+            && name != "meta-hybrid"
+            && name != defs::STORAGE_RW_DIR_NAME
+            && !active_ids.contains(name.as_ref())
+        {
             log::info!("Pruning orphaned module storage: {}", name);
 
             if path.is_dir() {
@@ -83,6 +146,257 @@ fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()>
     Ok(())
 }
 
+fn apply_replace_markers(module_id: &str, module_root: &Path, userxattr: bool) {
+    // The sentinel is only an intent, the synced tree carries it as overlay opacity instead.
+    // Depth 3 is <module>/<partition>/<dir>/.replace, a marker directly inside a partition
+    // would hide the whole partition and is never honored, but it is still stripped so it
+    // cannot surface as a stray file
+    let markers: Vec<_> = WalkDir::new(module_root)
+        .min_depth(2)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file() && e.file_name() == defs::REPLACE_DIR_FILE_NAME)
+        .map(|e| (e.depth(), e.into_path()))
+        .collect();
+
+    for (depth, marker) in markers {
+        if let Some(dir) = marker.parent() {
+            if depth < 3 {
+                log::warn!(
+                    "Ignoring replace marker on partition root for {}: {}",
+                    module_id,
+                    dir.display()
+                );
+            } else if let Err(e) = utils::set_overlay_opaque(dir, userxattr) {
+                log::warn!("Failed to apply replace for {}: {:#}", module_id, e);
+            } else {
+                log::debug!("Marked opaque for {}: {}", module_id, dir.display());
+            }
+        }
+
+        if let Err(e) = fs::remove_file(&marker) {
+            log::warn!(
+                "Failed to remove replace marker {}: {}",
+                marker.display(),
+                e
+            );
+        }
+    }
+}
+
+pub struct SyncMismatch {
+    pub module_id: String,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+pub fn verify_sync(
+    modules: &[Module],
+    target_base: &Path,
+    compare_content: bool,
+) -> Vec<SyncMismatch> {
+    log::info!(
+        "Verifying synced modules ({})",
+        if compare_content { "content" } else { "size" }
+    );
+
+    let mut mismatches: Vec<SyncMismatch> = modules
+        .par_iter()
+        .filter(|m| !matches!(m.rules.default_mode, MountMode::Magic) && has_partition_content(m))
+        .flat_map_iter(|m| verify_module(m, &target_base.join(&m.id), compare_content))
+        .collect();
+
+    mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+
+    mismatches
+}
+
+fn verify_module(module: &Module, dst: &Path, compare_content: bool) -> Vec<SyncMismatch> {
+    let mut mismatches = Vec::new();
+
+    for entry in WalkDir::new(&module.source_path).into_iter().flatten() {
+        // Replace markers are consumed by the sync itself
+        if !entry.file_type().is_file() || entry.file_name() == defs::REPLACE_DIR_FILE_NAME {
+            continue;
+        }
+
+        let Ok(relative) = entry.path().strip_prefix(&module.source_path) else {
+            continue;
+        };
+
+        let target = dst.join(relative);
+
+        let reason = match (entry.metadata(), fs::symlink_metadata(&target)) {
+            (Ok(_), Err(_)) => Some("missing".to_string()),
+            (Ok(_), Ok(copy)) if !copy.is_file() => Some("not a regular file".to_string()),
+            (Ok(orig), Ok(copy)) if orig.len() != copy.len() => {
+                Some(format!("size {} != {}", copy.len(), orig.len()))
+            }
+            (Ok(_), Ok(_)) if compare_content && !files_identical(entry.path(), &target) => {
+                Some("content differs".to_string())
+            }
+            _ => None,
+        };
+
+        if let Some(reason) = reason {
+            mismatches.push(SyncMismatch {
+                module_id: module.id.clone(),
+                path: target,
+                reason,
+            });
+        }
+    }
+
+    mismatches
+}
+
+fn files_identical(a: &Path, b: &Path) -> bool {
+    let (Ok(a), Ok(b)) = (File::open(a), File::open(b)) else {
+        return false;
+    };
+
+    let mut a = BufReader::new(a);
+
+    let mut b = BufReader::new(b);
+
+    let mut buf_a = [0u8; 64 * 1024];
+
+    let mut buf_b = [0u8; 64 * 1024];
+
+    loop {
+        let Ok(n) = a.read(&mut buf_a) else {
+            return false;
+        };
+
+        if n == 0 {
+            return b.read(&mut buf_b).is_ok_and(|m| m == 0);
+        }
+
+        if b.read_exact(&mut buf_b[..n]).is_err() || buf_a[..n] != buf_b[..n] {
+            return false;
+        }
+    }
+}
+
+fn prop_stamp(module_root: &Path) -> String {
+    fs::metadata(module_root.join("module.prop"))
+        .map(|m| format!("{}:{}", m.len(), m.mtime()))
+        .unwrap_or_default()
+}
+
+fn collect_stats(module_root: &Path, extra_partitions: &[String]) -> ModuleStats {
+    let mut stats = ModuleStats::default();
+
+    let mut visited = 0u64;
+
+    let partitions = defs::BUILTIN_PARTITIONS
+        .iter()
+        .copied()
+        .chain(extra_partitions.iter().map(String::as_str));
+
+    for partition in partitions {
+        let part_path = module_root.join(partition);
+
+        if !part_path.is_dir() {
+            continue;
+        }
+
+        for entry in WalkDir::new(&part_path)
+            .min_depth(1)
+            .max_depth(defs::MAX_TREE_DEPTH)
+            .into_iter()
+            .flatten()
+        {
+            visited += 1;
+
+            if visited > STATS_MAX_ENTRIES {
+                stats.truncated = true;
+
+                return stats;
+            }
+
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            stats.file_count += 1;
+
+            if entry.file_type().is_file() {
+                stats.total_size_bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+        }
+    }
+
+    stats
+}
+
+// Entries are keyed by the module.prop size and mtime, the same signal the sync uses to
+// detect an updated module, so only changed modules are walked again
+pub fn module_stats(
+    modules: &[Module],
+    extra_partitions: &[String],
+) -> HashMap<String, ModuleStats> {
+    let mut manifest: SyncManifest = fs::read_to_string(defs::SYNC_MANIFEST_FILE)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    let refreshed: Vec<(String, ManifestEntry)> = modules
+        .par_iter()
+        .filter_map(|module| {
+            let stamp = prop_stamp(&module.source_path);
+
+            if !stamp.is_empty()
+                && manifest
+                    .modules
+                    .get(&module.id)
+                    .is_some_and(|entry| entry.prop_stamp == stamp)
+            {
+                return None;
+            }
+
+            Some((
+                module.id.clone(),
+                ManifestEntry {
+                    prop_stamp: stamp,
+                    stats: collect_stats(&module.source_path, extra_partitions),
+                },
+            ))
+        })
+        .collect();
+
+    if !refreshed.is_empty() {
+        manifest.modules.extend(refreshed);
+
+        match serde_json::to_string(&manifest) {
+            Ok(content) => {
+                if let Err(e) = utils::atomic_write(defs::SYNC_MANIFEST_FILE, content) {
+                    log::warn!("Failed to write sync manifest: {:#}", e);
+                }
+            }
+            Err(e) => log::warn!("Failed to serialize sync manifest: {}", e),
+        }
+    }
+
+    modules
+        .iter()
+        .filter_map(|module| {
+            manifest
+                .modules
+                .get(&module.id)
+                .map(|entry| (module.id.clone(), entry.stats.clone()))
+        })
+        .collect()
+}
+
+fn has_partition_content(module: &Module) -> bool {
+    defs::BUILTIN_PARTITIONS.iter().any(|p| {
+        let part_path = module.source_path.join(p);
+
+        part_path.exists() && has_files_recursive(&part_path)
+    })
+}
+
 fn should_sync(src: &Path, dst: &Path) -> bool {
     if !dst.exists() {
         return true;
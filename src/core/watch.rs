@@ -0,0 +1,78 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rustix::{
+    fd::OwnedFd,
+    fs::inotify::{self, CreateFlags, WatchFlags},
+    io::{self, Errno},
+};
+use walkdir::WalkDir;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn watch_flags() -> WatchFlags {
+    WatchFlags::CREATE
+        | WatchFlags::DELETE
+        | WatchFlags::MODIFY
+        | WatchFlags::ATTRIB
+        | WatchFlags::CLOSE_WRITE
+        | WatchFlags::MOVED_FROM
+        | WatchFlags::MOVED_TO
+}
+
+// inotify is not recursive, every directory needs its own watch. Re-adding an existing one is
+// a no-op, so this is also how directories created since the last pass get picked up
+fn watch_tree(fd: &OwnedFd, root: &Path) {
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_dir())
+    {
+        if let Err(e) = inotify::add_watch(fd, entry.path(), watch_flags()) {
+            log::debug!("Failed to watch {}: {}", entry.path().display(), e);
+        }
+    }
+}
+
+// Runs until the process is interrupted. A burst of events (an adb push, a rebuild) only
+// triggers on_change once the tree has been quiet for `debounce`
+pub fn run(root: &Path, debounce: Duration, mut on_change: impl FnMut()) -> Result<()> {
+    let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)
+        .context("Failed to initialize inotify")?;
+
+    watch_tree(&fd, root);
+
+    let mut buf = [0u8; 4096];
+
+    let mut last_event: Option<Instant> = None;
+
+    loop {
+        match io::read(&fd, &mut buf[..]) {
+            Ok(_) => {
+                last_event = Some(Instant::now());
+
+                continue;
+            }
+            Err(Errno::AGAIN) => {}
+            Err(Errno::INTR) => continue,
+            Err(e) => return Err(e).context("Failed to read inotify events"),
+        }
+
+        if last_event.is_some_and(|t| t.elapsed() >= debounce) {
+            last_event = None;
+
+            watch_tree(&fd, root);
+
+            on_change();
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
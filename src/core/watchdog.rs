@@ -0,0 +1,60 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use crate::core::{progress, state};
+
+const WATCHDOG_EXIT_CODE: i32 = 124;
+
+pub struct Watchdog {
+    _disarm: Sender<()>,
+}
+
+impl Watchdog {
+    pub fn arm(phase: &'static str, timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::channel::<()>();
+
+        thread::spawn(move || {
+            // A hung mount syscall cannot be interrupted, the only way to let boot continue is
+            // to exit and leave the Ratoon counter engaged for recovery on the next boot
+            if let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(timeout) {
+                let current = progress::current()
+                    .map(|p| {
+                        format!(
+                            "{} {}/{} module={} target={}",
+                            p.phase,
+                            p.done,
+                            p.total,
+                            p.module.as_deref().unwrap_or("-"),
+                            p.target.as_deref().unwrap_or("-")
+                        )
+                    })
+                    .unwrap_or_else(|| "unknown".to_string());
+
+                let message = format!(
+                    "[CRITICAL] Watchdog: {} phase exceeded {}s, stuck at: {}. Aborting.",
+                    phase,
+                    timeout.as_secs(),
+                    current
+                );
+
+                log::error!("{}", message);
+
+                eprintln!("{}", message);
+
+                state::RuntimeState::record_phase(state::PHASE_WATCHDOG_TIMEOUT);
+
+                state::record_stage(state::STAGE_FAILED);
+
+                std::process::exit(WATCHDOG_EXIT_CODE);
+            }
+        });
+
+        Self { _disarm: tx }
+    }
+}
@@ -1,11 +1,17 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{conf::config::WinnowingTable, core::planner::ConflictEntry};
+use crate::{
+    conf::config::{WinnowingConfig, WinnowingStrategy},
+    core::planner::{ConflictEntry, ConflictReport},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChaffConflict {
@@ -13,38 +19,77 @@ pub struct ChaffConflict {
     pub contenders: Vec<String>,
     pub selected: String,
     pub is_forced: bool,
+    #[serde(default)]
+    pub resolved_by: String,
 }
 
-pub fn sift_conflicts(conflicts: Vec<ConflictEntry>, table: &WinnowingTable) -> Vec<ChaffConflict> {
+fn forced_winner(c: &ConflictEntry, table: &WinnowingConfig) -> Option<String> {
+    let path_str = format!("/{}/{}", c.partition, c.relative_path);
+
+    table
+        .get(Path::new(&path_str))
+        .filter(|forced| c.contending_modules.contains(forced))
+        .cloned()
+}
+
+pub fn sift_conflicts(
+    conflicts: Vec<ConflictEntry>,
+    table: &WinnowingConfig,
+) -> Vec<ChaffConflict> {
     conflicts
         .into_iter()
         .map(|c| {
             let path_str = format!("/{}/{}", c.partition, c.relative_path);
 
-            let forced_module = table.get_preferred_module(Path::new(&path_str));
+            let forced_module = forced_winner(&c, table);
 
-            let selected = if let Some(forced) = &forced_module {
-                if c.contending_modules.contains(forced) {
-                    forced.clone()
-                } else {
-                    c.contending_modules
-                        .last()
-                        .unwrap_or(&"unknown".to_string())
-                        .clone()
-                }
-            } else {
-                c.contending_modules
-                    .last()
-                    .unwrap_or(&"unknown".to_string())
-                    .clone()
+            let fallback = match table.strategy {
+                WinnowingStrategy::First => c.contending_modules.first(),
+                WinnowingStrategy::Last => c.contending_modules.last(),
+                WinnowingStrategy::Alphabetical => c.contending_modules.iter().min(),
             };
 
+            let selected = forced_module
+                .clone()
+                .or_else(|| fallback.cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+
             ChaffConflict {
                 path: PathBuf::from(path_str),
                 contenders: c.contending_modules,
                 selected,
                 is_forced: forced_module.is_some(),
+                resolved_by: if forced_module.is_some() {
+                    "rule".to_string()
+                } else {
+                    table.strategy.as_str().to_string()
+                },
             }
         })
         .collect()
 }
+
+// Contenders are listed in lowerdir order, so without a forcing rule the first one is the
+// top overlay layer and the module actually visible at the path, whatever strategy the
+// conflict report uses to suggest a pick
+pub fn find_shadowed_modules(report: ConflictReport, table: &WinnowingConfig) -> Vec<String> {
+    let mut winners: HashSet<String> = report.uncontested_modules;
+
+    for c in &report.details {
+        if let Some(winner) =
+            forced_winner(c, table).or_else(|| c.contending_modules.first().cloned())
+        {
+            winners.insert(winner);
+        }
+    }
+
+    let mut shadowed: Vec<String> = report
+        .layered_modules
+        .into_iter()
+        .filter(|id| !winners.contains(id))
+        .collect();
+
+    shadowed.sort();
+
+    shadowed
+}
@@ -5,13 +5,20 @@ pub const DEFAULT_HYBRID_MNT_DIR: &str = "/debug_ramdisk";
 pub const BASE_DIR: &str = "/data/adb/meta-hybrid/";
 pub const RUN_DIR: &str = "/data/adb/meta-hybrid/run/";
 pub const STATE_FILE: &str = "/data/adb/meta-hybrid/run/daemon_state.json";
+pub const PID_FILE: &str = "/data/adb/meta-hybrid/run/daemon.pid";
+pub const DAEMON_LOCK_FILE: &str = "/data/adb/meta-hybrid/run/daemon.lock";
 pub const DAEMON_LOG_FILE: &str = "/data/adb/meta-hybrid/daemon.log";
+pub const USER_RULES_DIR: &str = "/data/adb/meta-hybrid/rules";
+pub const HOOKS_DIR: &str = "/data/adb/meta-hybrid/hooks";
+pub const PROGRESS_FILE: &str = "/data/adb/meta-hybrid/progress.json";
+pub const STAGE_FILE: &str = "/data/adb/meta-hybrid/stage";
+pub const SYNC_MANIFEST_FILE: &str = "/data/adb/meta-hybrid/sync_manifest.json";
 pub const DISABLE_FILE_NAME: &str = "disable";
 pub const REMOVE_FILE_NAME: &str = "remove";
 pub const SKIP_MOUNT_FILE_NAME: &str = "skip_mount";
 pub const OVERLAY_SOURCE: &str = "KSU";
-pub const KSU_OVERLAY_SOURCE: &str = OVERLAY_SOURCE;
 pub const SYSTEM_RW_DIR: &str = "/data/adb/meta-hybrid/rw";
+pub const STORAGE_RW_DIR_NAME: &str = ".rw";
 pub const MODULE_PROP_FILE: &str = "/data/adb/modules/meta-hybrid/module.prop";
 pub const MODULES_DIR: &str = "/data/adb/modules";
 pub const BUILTIN_PARTITIONS: &[&str] = &[
@@ -26,3 +33,6 @@ pub const BUILTIN_PARTITIONS: &[&str] = &[
 pub const REPLACE_DIR_FILE_NAME: &str = ".replace";
 pub const REPLACE_DIR_XATTR: &str = "trusted.overlay.opaque";
 pub const TMPFS_CANDIDATES: &[&str] = &["/debug_ramdisk", "/patch_hw", "/oem", "/root", "/sbin"];
+pub const TMPFS_FALLBACK_PARENTS: &[&str] = &["/dev", "/mnt"];
+pub const TMPFS_FALLBACK_NAME: &str = ".hybrid_tmp";
+pub const MAX_TREE_DEPTH: usize = 64;
@@ -0,0 +1,48 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+
+// Failure classes callers branch on. They travel inside anyhow::Error like any other cause, so
+// context added on the way up to the CLI never hides them from MountError::find
+#[derive(Debug)]
+pub enum MountError {
+    Storage(String),
+    OverlayUnsupported(String),
+    ConfigInvalid(Vec<String>),
+    KsuDriverMissing,
+}
+
+impl MountError {
+    pub fn find(err: &anyhow::Error) -> Option<&MountError> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<MountError>())
+    }
+
+    // Only the errnos that say something about overlayfs itself or the backing storage are
+    // classified, everything else keeps its raw errno for retry_mount
+    pub fn from_overlay_errno(errno: rustix::io::Errno) -> anyhow::Error {
+        match errno {
+            rustix::io::Errno::NODEV => {
+                Self::OverlayUnsupported("kernel does not provide overlayfs".to_string()).into()
+            }
+            rustix::io::Errno::NOSPC | rustix::io::Errno::DQUOT => {
+                Self::Storage(format!("overlay layers are out of space: {}", errno)).into()
+            }
+            _ => errno.into(),
+        }
+    }
+}
+
+impl fmt::Display for MountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Storage(reason) => write!(f, "Storage backend failure: {}", reason),
+            Self::OverlayUnsupported(reason) => write!(f, "OverlayFS unsupported: {}", reason),
+            Self::ConfigInvalid(errors) => write!(f, "Config rejected: {}", errors.join("; ")),
+            Self::KsuDriverMissing => write!(f, "KSU driver not available"),
+        }
+    }
+}
+
+impl std::error::Error for MountError {}
@@ -4,20 +4,26 @@
 mod conf;
 mod core;
 mod defs;
+mod error;
 mod mount;
+#[cfg(test)]
+mod test_utils;
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod try_umount;
 mod utils;
 
 use core::{OryzaEngine, executor, granary, inventory, planner, winnow};
-use std::path::{Path, PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use conf::{
     cli::{Cli, Commands},
     cli_handlers,
-    config::{CONFIG_FILE_DEFAULT, Config},
+    config::{CONFIG_FILE_DEFAULT, Config, LogFormat},
 };
 use mimalloc::MiMalloc;
 
@@ -55,24 +61,80 @@ fn load_config(cli: &Cli) -> Result<Config> {
     }
 }
 
+fn apply_auto_partitions(config: &mut Config) {
+    if !config.auto_partitions {
+        return;
+    }
+
+    match utils::discover_partitions() {
+        Ok(discovered) => {
+            log::info!(">> Auto-discovered partitions: {:?}", discovered);
+
+            for partition in discovered {
+                if !config.partitions.contains(&partition) {
+                    config.partitions.push(partition);
+                }
+            }
+        }
+        Err(e) => log::warn!("Partition discovery failed: {:#}", e),
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     if let Some(command) = &cli.command {
         match command {
-            Commands::GenConfig { output } => cli_handlers::handle_gen_config(output)?,
+            Commands::GenConfig { output, minimal } => {
+                cli_handlers::handle_gen_config(output, *minimal)?
+            }
             Commands::ShowConfig => cli_handlers::handle_show_config(&cli)?,
+            Commands::ConfigSchema => cli_handlers::handle_config_schema()?,
             Commands::SaveConfig { payload } => cli_handlers::handle_save_config(&cli, payload)?,
-            Commands::SaveRules { module, payload } => {
-                cli_handlers::handle_save_rules(module, payload)?
-            }
+            Commands::SaveRules {
+                module,
+                payload,
+                merge,
+            } => cli_handlers::handle_save_rules(module, payload, *merge)?,
             Commands::Storage => cli_handlers::handle_storage()?,
+            Commands::Status => cli_handlers::handle_status(&cli)?,
             Commands::Modules => cli_handlers::handle_modules(&cli)?,
-            Commands::Conflicts => cli_handlers::handle_conflicts(&cli)?,
+            Commands::Toggle {
+                module,
+                enable,
+                preview,
+            } => cli_handlers::handle_toggle(&cli, module, *enable, *preview)?,
+            Commands::DisableAll { confirm } => cli_handlers::handle_disable_all(&cli, *confirm)?,
+            Commands::EnableAll { confirm } => cli_handlers::handle_enable_all(&cli, *confirm)?,
+            Commands::CheckUpdates => cli_handlers::handle_check_updates(&cli)?,
+            Commands::Conflicts { simulate_rule } => {
+                cli_handlers::handle_conflicts(&cli, simulate_rule)?
+            }
             Commands::Diagnostics => cli_handlers::handle_diagnostics(&cli)?,
-            Commands::SystemAction { action, value } => {
-                cli_handlers::handle_system_action(&cli, action, value.as_deref())?
+            Commands::Watch { debounce_ms } => cli_handlers::handle_watch(&cli, *debounce_ms)?,
+            Commands::ExportMounts => cli_handlers::handle_export_mounts(&cli)?,
+            Commands::Tree { module } => cli_handlers::handle_tree(&cli, module)?,
+            Commands::Benchmark => cli_handlers::handle_benchmark(&cli)?,
+            Commands::Diff { snapshot, save } => cli_handlers::handle_diff(&cli, snapshot, *save)?,
+            Commands::Log { lines, json } => cli_handlers::handle_log(*lines, *json)?,
+            Commands::Prop { module, key, value } => {
+                cli_handlers::handle_prop(&cli, module, key, value.as_deref())?
+            }
+            Commands::Export { output } => cli_handlers::handle_export(&cli, output)?,
+            Commands::Import { input } => cli_handlers::handle_import(&cli, input)?,
+            Commands::Repair { confirm } => cli_handlers::handle_repair(&cli, *confirm)?,
+            Commands::Clean {
+                keep_silos,
+                confirm,
+            } => cli_handlers::handle_clean(&cli, *keep_silos, *confirm)?,
+            Commands::Nuke { target, confirm } => {
+                cli_handlers::handle_nuke(&cli, target, *confirm)?
             }
+            Commands::SystemAction {
+                action,
+                value,
+                confirm,
+            } => cli_handlers::handle_system_action(&cli, action, value.as_deref(), *confirm)?,
         }
 
         return Ok(());
@@ -80,50 +142,91 @@ fn main() -> Result<()> {
 
     let mut config = load_config(&cli)?;
 
-    config.merge_with_cli(
-        cli.moduledir.clone(),
-        cli.mountsource.clone(),
-        cli.verbose,
-        cli.partitions.clone(),
-        cli.dry_run,
-    );
+    config.merge_with_cli(&cli);
 
-    if !config.dry_run
-        && let Err(e) = granary::engage_ratoon_protocol()
-    {
-        log::error!("Failed to engage Ratoon Protocol: {}", e);
-    }
+    config.apply_root_backend();
 
-    if utils::check_zygisksu_enforce_status() {
-        if config.allow_umount_coexistence {
-            if config.verbose {
-                println!(
-                    ">> ZygiskSU Enforce!=0 detected, but Umount Coexistence enabled. Respecting \
-                     user config."
-                );
-            }
-        } else {
-            if config.verbose {
-                println!(">> ZygiskSU Enforce!=0 detected. Forcing DISABLE_UMOUNT to TRUE.");
+    cli_handlers::apply_module_filter(&cli, &mut config);
+
+    // Held until exit. A dry run mounts nothing, so it never waits on a live daemon
+    let _daemon_lock = if config.dry_run {
+        None
+    } else {
+        match utils::acquire_daemon_lock()? {
+            Some(lock) => Some(lock),
+            None => {
+                eprintln!("Another Meta-Hybrid Mount daemon is already running, exiting.");
+
+                return Ok(());
             }
+        }
+    };
+
+    let previous_phase = core::state::RuntimeState::load()
+        .map(|s| s.phase)
+        .unwrap_or_default();
+
+    let rolled_back = if config.dry_run {
+        false
+    } else {
+        granary::engage_ratoon_protocol().unwrap_or_else(|e| {
+            log::error!("Failed to engage Ratoon Protocol: {}", e);
+
+            false
+        })
+    };
 
-            config.disable_umount = true;
+    let zygisksu_enforce = utils::check_zygisksu_enforce_status(&config.moduledir);
+
+    if zygisksu_enforce && config.verbose && !config.disable_umount {
+        if config.allow_umount_coexistence {
+            println!(
+                ">> ZygiskSU Enforce!=0 detected, but Umount Coexistence enabled. Respecting user \
+                 config."
+            );
+        } else {
+            println!(">> ZygiskSU Enforce!=0 detected. Forcing DISABLE_UMOUNT to TRUE.");
         }
     }
 
+    config.disable_umount = config.umount_disabled(zygisksu_enforce);
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    try_umount::init(&config);
+
     if config.dry_run {
-        env_logger::builder()
-            .filter_level(if config.verbose {
-                log::LevelFilter::Debug
-            } else {
-                log::LevelFilter::Info
-            })
-            .init();
+        let mut builder = env_logger::builder();
+
+        builder.filter_level(if config.verbose {
+            log::LevelFilter::Debug
+        } else {
+            log::LevelFilter::Info
+        });
+
+        if config.log_format == LogFormat::Json {
+            builder.format(|buf, record| {
+                writeln!(
+                    buf,
+                    "{}",
+                    utils::json_log_line(
+                        record.level().as_str(),
+                        record.target(),
+                        &record.args().to_string()
+                    )
+                )
+            });
+        }
+
+        builder.init();
 
         log::info!(":: DRY-RUN / DIAGNOSTIC MODE ::");
 
-        let module_list =
-            inventory::scan(&config.moduledir, &config).context("Inventory scan failed")?;
+        apply_auto_partitions(&mut config);
+
+        let scan =
+            inventory::scan_report(&config.moduledir, &config).context("Inventory scan failed")?;
+
+        let module_list = scan.modules;
 
         log::info!(">> Inventory: Found {} modules", module_list.len());
 
@@ -159,7 +262,9 @@ fn main() -> Result<()> {
 
         log::info!(">> Running System Diagnostics...");
 
-        let issues = executor::diagnose_plan(&plan);
+        let mut issues = executor::diagnose_plan(&plan, &config);
+
+        issues.extend(scan.issues.into_iter().map(executor::DiagnosticIssue::from));
 
         let mut critical_count = 0;
 
@@ -195,18 +300,41 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    let _log_guard = utils::init_logging(config.verbose, Path::new(defs::DAEMON_LOG_FILE))
-        .context("Failed to initialize logging")?;
+    core::state::record_stage(core::state::STAGE_INIT);
+
+    let _log_guard = utils::init_logging(
+        config.verbose,
+        Path::new(defs::DAEMON_LOG_FILE),
+        config.max_log_size_kb,
+        config.log_format,
+    )
+    .context("Failed to initialize logging")?;
 
     let camouflage_name = utils::random_kworker_name();
 
-    if let Err(e) = utils::camouflage_process(&camouflage_name) {
+    if config.camouflage
+        && let Err(e) = utils::camouflage_process(&camouflage_name)
+    {
         log::warn!("Failed to camouflage process: {:#}", e);
     }
 
     log::info!(">> Initializing Meta-Hybrid Mount Daemon...");
 
-    log::debug!("Process camouflaged as: {}", camouflage_name);
+    // Hooks only ever run once the process is camouflaged
+    if rolled_back {
+        core::hooks::run(core::hooks::ON_ROLLBACK, config.hook_timeout_secs);
+    }
+
+    if !previous_phase.is_empty() && previous_phase != core::state::PHASE_FINALIZED {
+        log::warn!(
+            "!! Previous run did not finish, last completed phase: {}",
+            previous_phase
+        );
+    }
+
+    if config.camouflage {
+        log::debug!("Process camouflaged as: {}", camouflage_name);
+    }
 
     if let Ok(version) = std::fs::read_to_string("/proc/sys/kernel/osrelease") {
         log::debug!("Kernel Version: {}", version.trim());
@@ -216,28 +344,50 @@ fn main() -> Result<()> {
         log::warn!("!! Umount is DISABLED via config.");
     }
 
+    apply_auto_partitions(&mut config);
+
+    for warning in config.validate() {
+        log::warn!("!! Config: {}", warning);
+    }
+
     utils::ensure_dir_exists(defs::RUN_DIR)
         .with_context(|| format!("Failed to create run directory: {}", defs::RUN_DIR))?;
 
+    // The comm may be camouflaged, the pid file is how tooling finds the daemon
+    if let Err(e) = utils::atomic_write(defs::PID_FILE, format!("{}\n", std::process::id())) {
+        log::warn!("Failed to write pid file: {:#}", e);
+    }
+
+    if config.use_private_namespace {
+        utils::enter_private_mount_namespace()
+            .context("Failed to enter a private mount namespace")?;
+
+        log::warn!(">> Mounting inside a private namespace. Mounts are not visible system-wide.");
+    }
+
     let mnt_base = PathBuf::from(&config.hybrid_mnt_dir);
 
-    let img_path = Path::new(defs::BASE_DIR).join("modules.img");
+    let img_path = config.image_path();
 
     if let Err(e) = granary::create_silo(&config, "Boot Backup", "Automatic Pre-Mount") {
         log::warn!("Granary: Failed to create boot snapshot: {}", e);
     }
 
-    OryzaEngine::new(config)
-        .init_storage(&mnt_base, &img_path)
-        .context("Failed to initialize storage")?
-        .scan_and_sync()
-        .context("Failed to scan and sync modules")?
-        .generate_plan()
-        .context("Failed to generate mount plan")?
-        .execute()
-        .context("Failed to execute mount plan")?
-        .finalize()
-        .context("Failed to finalize boot sequence")?;
-
-    Ok(())
+    let engine = OryzaEngine::new(config);
+
+    let pipeline = || -> Result<()> {
+        engine
+            .init_storage(&mnt_base, &img_path)
+            .context("Failed to initialize storage")?
+            .scan_and_sync()
+            .context("Failed to scan and sync modules")?
+            .generate_plan()
+            .context("Failed to generate mount plan")?
+            .execute()
+            .context("Failed to execute mount plan")?
+            .finalize()
+            .context("Failed to finalize boot sequence")
+    };
+
+    pipeline().inspect_err(|_| core::state::record_stage(core::state::STAGE_FAILED))
 }
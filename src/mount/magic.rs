@@ -3,10 +3,10 @@
 
 use std::{
     collections::{HashMap, HashSet},
-    fs::{self, DirEntry, create_dir, read_dir, read_link},
+    fs::{self, DirEntry, create_dir, read_link},
     os::unix::fs::{MetadataExt, symlink},
     path::{Path, PathBuf},
-    sync::atomic::AtomicU32,
+    sync::{LazyLock, Mutex, atomic::AtomicU32},
 };
 
 use anyhow::{Context, Result, bail};
@@ -14,7 +14,7 @@ use rustix::{
     fs::{Gid, Mode, Uid, chmod, chown},
     mount::{
         MountFlags, MountPropagationFlags, UnmountFlags, mount, mount_bind, mount_change,
-        mount_move, mount_remount, unmount,
+        mount_move, mount_recursive_bind, mount_remount, unmount,
     },
 };
 
@@ -22,14 +22,52 @@ use rustix::{
 use crate::try_umount::send_unmountable;
 use crate::{
     defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME},
-    mount::node::{Node, NodeFileType},
-    utils::{ensure_dir_exists, lgetfilecon, lsetfilecon, validate_module_id},
+    mount::{
+        node::{Node, NodeFileType},
+        script::{MountScript, quote, quote_path},
+    },
+    utils::{ensure_dir_exists, is_mount_readonly, lgetfilecon, lsetfilecon, validate_module_id},
 };
 
 const ROOT_PARTITIONS: [&str; 4] = ["vendor", "system_ext", "product", "odm"];
 
 static MOUNTED_FILES: AtomicU32 = AtomicU32::new(0);
 static MOUNTED_SYMBOLS_FILES: AtomicU32 = AtomicU32::new(0);
+static MIRRORED_DIRS: AtomicU32 = AtomicU32::new(0);
+static WRITABLE_PATHS: LazyLock<Mutex<Vec<PathBuf>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+const RDONLY_REMOUNT_ATTEMPTS: u32 = 2;
+
+fn remount_readonly(path: &Path) -> bool {
+    for attempt in 1..=RDONLY_REMOUNT_ATTEMPTS {
+        if let Err(e) = mount_remount(path, MountFlags::RDONLY | MountFlags::BIND, "") {
+            log::warn!("make {} ro (attempt {attempt}): {e:#?}", path.display());
+            continue;
+        }
+
+        // Unknown (no mountinfo entry) is treated as success, only a visible rw flag fails
+        if is_mount_readonly(path) != Some(false) {
+            return true;
+        }
+
+        log::warn!(
+            "{} still mounted rw after remount (attempt {attempt})",
+            path.display()
+        );
+    }
+
+    false
+}
+
+fn record_writable(path: &Path) {
+    log::error!("!! {} is unexpectedly writable", path.display());
+
+    WRITABLE_PATHS.lock().unwrap().push(path.to_path_buf());
+}
+
+pub fn take_writable_paths() -> Vec<PathBuf> {
+    std::mem::take(&mut *WRITABLE_PATHS.lock().unwrap())
+}
 
 fn clone_symlink<S>(src: S, dst: S) -> Result<()>
 where
@@ -41,6 +79,8 @@ where
     Ok(())
 }
 
+// Only called for entries no module touches, so a stock dir is mirrored with one recursive
+// bind instead of a bind per file; this also never walks into bind mount loops
 fn mount_mirror<P>(path: P, work_dir_path: P, entry: &DirEntry) -> Result<()>
 where
     P: AsRef<Path>,
@@ -54,23 +94,30 @@ where
         mount_bind(&path, &work_dir_path)?;
     } else if file_type.is_dir() {
         create_dir(&work_dir_path)?;
-        let metadata = entry.metadata()?;
-        chmod(&work_dir_path, Mode::from_raw_mode(metadata.mode()))?;
-        chown(
-            &work_dir_path,
-            Some(Uid::from_raw(metadata.uid())),
-            Some(Gid::from_raw(metadata.gid())),
-        )?;
-        lsetfilecon(&work_dir_path, lgetfilecon(&path)?.as_str())?;
-        for entry in read_dir(&path)?.flatten() {
-            mount_mirror(&path, &work_dir_path, &entry)?;
-        }
+        mount_recursive_bind(&path, &work_dir_path)?;
+        MIRRORED_DIRS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     } else if file_type.is_symlink() {
         clone_symlink(&path, &work_dir_path)?;
     }
     Ok(())
 }
 
+// A partition dir may be a symlink inside the module (vendor -> system/vendor), but must
+// never resolve to a tree outside of it
+fn is_within_module(module: &Path, partition_dir: &Path) -> bool {
+    match (module.canonicalize(), partition_dir.canonicalize()) {
+        (Ok(module), Ok(part)) if part.starts_with(&module) => true,
+        _ => {
+            log::warn!(
+                "Rejecting {}: resolves outside module {}",
+                partition_dir.display(),
+                module.display()
+            );
+            false
+        }
+    }
+}
+
 fn process_module(
     path: &Path,
     extra_partitions: &[String],
@@ -103,7 +150,7 @@ fn process_module(
 
     if !is_excluded("system") {
         let mod_system = path.join("system");
-        if mod_system.is_dir() {
+        if mod_system.is_dir() && is_within_module(path, &mod_system) {
             system.collect_module_files(&mod_system)?;
         }
     }
@@ -113,7 +160,7 @@ fn process_module(
             continue;
         }
         let mod_part = path.join(partition);
-        if mod_part.is_dir() {
+        if mod_part.is_dir() && is_within_module(path, &mod_part) {
             let node = system
                 .children
                 .entry(partition.to_string())
@@ -141,7 +188,10 @@ fn process_module(
             let name = partition.clone();
             let mod_part = path.join(partition);
             // Fix: Collapsed if statement to satisfy clippy
-            if mod_part.is_dir() && (!path_of_system.exists() || path_of_system.is_symlink()) {
+            if mod_part.is_dir()
+                && (!path_of_system.exists() || path_of_system.is_symlink())
+                && is_within_module(path, &mod_part)
+            {
                 let node = root
                     .children
                     .entry(name)
@@ -153,12 +203,22 @@ fn process_module(
     Ok((root, system))
 }
 
+// `high` comes from the module earlier in the merge order and wins every contested node:
+// - a node claimed by a module (module_path set, or a whiteout) keeps its type and flags
+// - an unclaimed directory only takes over a low file or symlink while it has no children,
+//   mirroring the symlink-to-dir coercion process_module does for partitions
+// - only directories merge children, and a replace dir hides everything below it from
+//   lower modules, the same way an opaque upper dir does in overlayfs
 fn merge_nodes(high: &mut Node, low: Node) {
-    if high.module_path.is_none() {
+    let high_claimed = high.module_path.is_some() || high.file_type == NodeFileType::Whiteout;
+    if !high_claimed && (low.file_type == NodeFileType::Directory || high.children.is_empty()) {
         high.module_path = low.module_path;
         high.file_type = low.file_type;
         high.replace = low.replace;
     }
+    if high.file_type != NodeFileType::Directory || (high.replace && high.module_path.is_some()) {
+        return;
+    }
     for (name, low_child) in low.children {
         match high.children.entry(name) {
             std::collections::hash_map::Entry::Vacant(v) => {
@@ -171,10 +231,11 @@ fn merge_nodes(high: &mut Node, low: Node) {
     }
 }
 
-fn collect_module_files(
+pub fn collect_module_files(
     module_paths: &[PathBuf],
     extra_partitions: &[String],
     exclusions: &HashMap<PathBuf, HashSet<String>>,
+    symlink_policy: &HashMap<String, bool>,
 ) -> Result<Option<Node>> {
     let (mut final_root, mut final_system) = module_paths
         .iter()
@@ -194,6 +255,8 @@ fn collect_module_files(
     let has_content = !final_root.children.is_empty() || !final_system.children.is_empty();
 
     if has_content {
+        // Builtin defaults: a partition is attached to root only when /system/<part> is a
+        // symlink, except odm which is attached whenever /odm exists. Overridable per device.
         const BUILTIN_CHECKS: [(&str, bool); 4] = [
             ("vendor", true),
             ("system_ext", true),
@@ -201,7 +264,11 @@ fn collect_module_files(
             ("odm", false),
         ];
 
-        for (partition, require_symlink) in BUILTIN_CHECKS {
+        for (partition, default_require_symlink) in BUILTIN_CHECKS {
+            let require_symlink = symlink_policy
+                .get(partition)
+                .copied()
+                .unwrap_or(default_require_symlink);
             let path_of_root = Path::new("/").join(partition);
             let path_of_system = Path::new("/system").join(partition);
 
@@ -221,6 +288,29 @@ fn collect_module_files(
     }
 }
 
+fn needs_tmpfs(node: &Node, path: &Path) -> bool {
+    if node.replace && node.module_path.is_some() {
+        return true;
+    }
+
+    node.children.values().any(|child| {
+        let real_path = path.join(&child.name);
+        let need = match child.file_type {
+            NodeFileType::Symlink => true,
+            NodeFileType::Whiteout => real_path.exists(),
+            _ => {
+                if let Ok(metadata) = real_path.symlink_metadata() {
+                    let file_type = NodeFileType::from(metadata.file_type());
+                    file_type != child.file_type || file_type == NodeFileType::Symlink
+                } else {
+                    true
+                }
+            }
+        };
+        need && child.module_path.is_some()
+    })
+}
+
 struct MagicMount {
     node: Node,
     path: PathBuf,
@@ -284,8 +374,8 @@ impl MagicMount {
                 )
             })?;
 
-            if let Err(e) = mount_remount(target_path, MountFlags::RDONLY | MountFlags::BIND, "") {
-                log::warn!("make file {} ro: {e:#?}", target_path.display());
+            if !remount_readonly(target_path) {
+                record_writable(&self.path);
             }
 
             let mounted = MOUNTED_FILES.load(std::sync::atomic::Ordering::Relaxed) + 1;
@@ -309,32 +399,7 @@ impl MagicMount {
     }
 
     fn handle_directory(&mut self) -> Result<()> {
-        let mut create_tmpfs =
-            !self.has_tmpfs && self.node.replace && self.node.module_path.is_some();
-        if !self.has_tmpfs && !create_tmpfs {
-            for node in self.node.children.values() {
-                let real_path = self.path.join(&node.name);
-                let need = match node.file_type {
-                    NodeFileType::Symlink => true,
-                    NodeFileType::Whiteout => real_path.exists(),
-                    _ => {
-                        if let Ok(metadata) = real_path.symlink_metadata() {
-                            let file_type = NodeFileType::from(metadata.file_type());
-                            file_type != node.file_type || file_type == NodeFileType::Symlink
-                        } else {
-                            true
-                        }
-                    }
-                };
-                if need {
-                    if node.module_path.is_none() {
-                        continue;
-                    }
-                    create_tmpfs = true;
-                    break;
-                }
-            }
-        }
+        let create_tmpfs = !self.has_tmpfs && needs_tmpfs(&self.node, &self.path);
 
         let has_tmpfs = self.has_tmpfs || create_tmpfs;
 
@@ -397,12 +462,9 @@ impl MagicMount {
         }
 
         if create_tmpfs {
-            mount_remount(
-                &self.work_dir_path,
-                MountFlags::RDONLY | MountFlags::BIND,
-                "",
-            )
-            .ok();
+            if !remount_readonly(&self.work_dir_path) {
+                record_writable(&self.path);
+            }
             mount_move(&self.work_dir_path, &self.path)?;
             mount_change(&self.path, MountPropagationFlags::PRIVATE)?;
 
@@ -415,17 +477,208 @@ impl MagicMount {
     }
 }
 
+fn render_dir_attrs(script: &mut MountScript, target: &Path, source: &Path) -> Result<()> {
+    let metadata = source.metadata()?;
+    script.push(format!(
+        "chmod {:o} {}",
+        metadata.mode() & 0o7777,
+        quote_path(target)
+    ));
+    script.push(format!(
+        "chown {}:{} {}",
+        metadata.uid(),
+        metadata.gid(),
+        quote_path(target)
+    ));
+    script.push(format!(
+        "chcon {} {}",
+        quote(lgetfilecon(source)?),
+        quote_path(target)
+    ));
+    Ok(())
+}
+
+fn render_symlink(script: &mut MountScript, src: &Path, dst: &Path) -> Result<()> {
+    script.push(format!(
+        "ln -s {} {}",
+        quote_path(read_link(src)?),
+        quote_path(dst)
+    ));
+    script.push(format!(
+        "chcon -h {} {}",
+        quote(lgetfilecon(src)?),
+        quote_path(dst)
+    ));
+    Ok(())
+}
+
+fn render_mirror(script: &mut MountScript, path: &Path, work_dir_path: &Path) -> Result<()> {
+    let file_type = path.symlink_metadata()?.file_type();
+
+    if file_type.is_file() {
+        script.push(format!("touch {}", quote_path(work_dir_path)));
+        script.push(format!(
+            "mount --bind {} {}",
+            quote_path(path),
+            quote_path(work_dir_path)
+        ));
+    } else if file_type.is_dir() {
+        script.push(format!("mkdir {}", quote_path(work_dir_path)));
+        script.push(format!(
+            "mount --rbind {} {}",
+            quote_path(path),
+            quote_path(work_dir_path)
+        ));
+    } else if file_type.is_symlink() {
+        render_symlink(script, path, work_dir_path)?;
+    }
+    Ok(())
+}
+
+// Mirrors MagicMount::do_magic_mount decision for decision, but emits shell instead of mounting
+fn render_node(
+    script: &mut MountScript,
+    node: &Node,
+    parent: &Path,
+    work_parent: &Path,
+    has_tmpfs: bool,
+) -> Result<()> {
+    let path = parent.join(&node.name);
+    let work_dir_path = work_parent.join(&node.name);
+
+    match node.file_type {
+        NodeFileType::RegularFile => {
+            let Some(module_path) = &node.module_path else {
+                bail!("cannot mount root file {}!", path.display());
+            };
+            let target = if has_tmpfs {
+                script.push(format!("touch {}", quote_path(&work_dir_path)));
+                &work_dir_path
+            } else {
+                &path
+            };
+            script.push(format!(
+                "mount --bind {} {}",
+                quote_path(module_path),
+                quote_path(target)
+            ));
+            script.push(format!("mount -o remount,ro,bind {}", quote_path(target)));
+        }
+        NodeFileType::Symlink => {
+            let Some(module_path) = &node.module_path else {
+                bail!("cannot mount root symlink {}!", path.display());
+            };
+            render_symlink(script, module_path, &work_dir_path)?;
+        }
+        NodeFileType::Whiteout => {}
+        NodeFileType::Directory => {
+            let mut node = node.clone();
+            if node.replace && node.module_path.is_none() {
+                node.replace = false;
+            }
+
+            let create_tmpfs = !has_tmpfs && needs_tmpfs(&node, &path);
+            let has_tmpfs = has_tmpfs || create_tmpfs;
+
+            if has_tmpfs {
+                script.push(format!("mkdir -p {}", quote_path(&work_dir_path)));
+                if path.exists() {
+                    render_dir_attrs(script, &work_dir_path, &path)?;
+                } else if let Some(mp) = &node.module_path {
+                    render_dir_attrs(script, &work_dir_path, mp)?;
+                }
+            }
+
+            if create_tmpfs {
+                script.push(format!(
+                    "mount --bind {} {}",
+                    quote_path(&work_dir_path),
+                    quote_path(&work_dir_path)
+                ));
+            }
+
+            if path.exists() && !node.replace {
+                for entry in path.read_dir()?.flatten() {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if let Some(child) = node.children.remove(&name) {
+                        if !child.skip {
+                            render_node(script, &child, &path, &work_dir_path, has_tmpfs)?;
+                        }
+                    } else if has_tmpfs {
+                        render_mirror(script, &path.join(&name), &work_dir_path.join(&name))?;
+                    }
+                }
+            }
+
+            let mut children: Vec<_> = node.children.values().filter(|c| !c.skip).collect();
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+            for child in children {
+                render_node(script, child, &path, &work_dir_path, has_tmpfs)?;
+            }
+
+            if create_tmpfs {
+                script.push(format!(
+                    "mount -o remount,ro,bind {}",
+                    quote_path(&work_dir_path)
+                ));
+                script.push(format!(
+                    "mount --move {} {}",
+                    quote_path(&work_dir_path),
+                    quote_path(&path)
+                ));
+                script.push(format!("mount --make-private {}", quote_path(&path)));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn render_partitions(
+    script: &mut MountScript,
+    tmp_path: &Path,
+    module_paths: &[PathBuf],
+    mount_source: &str,
+    extra_partitions: &[String],
+    exclusions: &HashMap<PathBuf, HashSet<String>>,
+    symlink_policy: &HashMap<String, bool>,
+) -> Result<()> {
+    let Some(root) =
+        collect_module_files(module_paths, extra_partitions, exclusions, symlink_policy)?
+    else {
+        script.comment("magic mount: nothing to mount");
+        return Ok(());
+    };
+
+    let tmp_dir = tmp_path.join("workdir");
+    script.push(format!("mkdir -p {}", quote_path(&tmp_dir)));
+    script.push(format!(
+        "mount -t tmpfs {} {}",
+        quote(mount_source),
+        quote_path(&tmp_dir)
+    ));
+    script.push(format!("mount --make-private {}", quote_path(&tmp_dir)));
+
+    render_node(script, &root, Path::new("/"), &tmp_dir, false)?;
+
+    script.push(format!("umount -l {}", quote_path(&tmp_dir)));
+    script.push(format!("rmdir {}", quote_path(&tmp_dir)));
+    Ok(())
+}
+
 pub fn mount_partitions(
     tmp_path: &Path,
     module_paths: &[PathBuf],
     mount_source: &str,
     extra_partitions: &[String],
     exclusions: HashMap<PathBuf, HashSet<String>>,
+    symlink_policy: &HashMap<String, bool>,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
     #[cfg(not(any(target_os = "linux", target_os = "android")))] _disable_umount: bool,
 ) -> Result<()> {
-    if let Some(root) = collect_module_files(module_paths, extra_partitions, &exclusions)? {
-        let tmp_dir = tmp_path.join("workdir");
+    if let Some(root) =
+        collect_module_files(module_paths, extra_partitions, &exclusions, symlink_policy)?
+    {
+        let tmp_dir = tmp_path.join(format!("workdir.{}", std::process::id()));
         ensure_dir_exists(&tmp_dir)?;
 
         mount(
@@ -460,10 +713,12 @@ pub fn mount_partitions(
 
         let files = MOUNTED_FILES.load(std::sync::atomic::Ordering::Relaxed);
         let symlinks = MOUNTED_SYMBOLS_FILES.load(std::sync::atomic::Ordering::Relaxed);
+        let mirrored = MIRRORED_DIRS.load(std::sync::atomic::Ordering::Relaxed);
         log::info!(
-            "Magic Mount: {} files, {} symlinks processed.",
+            "Magic Mount: {} files, {} symlinks processed, {} stock dirs mirrored whole.",
             files,
-            symlinks
+            symlinks,
+            mirrored
         );
 
         result
@@ -471,3 +726,215 @@ pub fn mount_partitions(
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{defs::REPLACE_DIR_FILE_NAME, test_utils::ScratchDir};
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    fn entry(name: &str, file_type: NodeFileType, module: &str) -> Node {
+        let source = Path::new("/data/adb/modules").join(module).join(name);
+
+        Node::new_entry(name, file_type, Some(source))
+    }
+
+    fn with_child(mut parent: Node, child: Node) -> Node {
+        parent.children.insert(child.name.clone(), child);
+        parent
+    }
+
+    fn source_of(node: &Node) -> Option<&Path> {
+        node.module_path.as_deref()
+    }
+
+    #[test]
+    fn same_file_keeps_earlier_module() {
+        let mut high = with_child(
+            Node::new_root("system"),
+            entry("build.prop", NodeFileType::RegularFile, "a"),
+        );
+        let low = with_child(
+            Node::new_root("system"),
+            entry("build.prop", NodeFileType::RegularFile, "b"),
+        );
+
+        merge_nodes(&mut high, low);
+
+        let prop = &high.children["build.prop"];
+        assert_eq!(prop.file_type, NodeFileType::RegularFile);
+        assert_eq!(
+            source_of(prop),
+            Some(Path::new("/data/adb/modules/a/build.prop"))
+        );
+    }
+
+    #[test]
+    fn claimed_file_is_not_replaced_by_lower_dir() {
+        let mut high = entry("etc", NodeFileType::RegularFile, "a");
+        let low = with_child(
+            entry("etc", NodeFileType::Directory, "b"),
+            entry("hosts", NodeFileType::RegularFile, "b"),
+        );
+
+        merge_nodes(&mut high, low);
+
+        assert_eq!(high.file_type, NodeFileType::RegularFile);
+        assert_eq!(source_of(&high), Some(Path::new("/data/adb/modules/a/etc")));
+        assert!(high.children.is_empty());
+    }
+
+    #[test]
+    fn claimed_dir_is_not_replaced_by_lower_file() {
+        let mut high = with_child(
+            entry("etc", NodeFileType::Directory, "a"),
+            entry("hosts", NodeFileType::RegularFile, "a"),
+        );
+        let low = entry("etc", NodeFileType::RegularFile, "b");
+
+        merge_nodes(&mut high, low);
+
+        assert_eq!(high.file_type, NodeFileType::Directory);
+        assert_eq!(source_of(&high), Some(Path::new("/data/adb/modules/a/etc")));
+        assert!(high.children.contains_key("hosts"));
+    }
+
+    #[test]
+    fn whiteout_is_never_overwritten() {
+        let mut high = Node::new_entry("app", NodeFileType::Whiteout, None);
+        let low = with_child(
+            entry("app", NodeFileType::Directory, "b"),
+            entry("Foo.apk", NodeFileType::RegularFile, "b"),
+        );
+
+        merge_nodes(&mut high, low);
+
+        assert_eq!(high.file_type, NodeFileType::Whiteout);
+        assert!(high.module_path.is_none());
+        assert!(high.children.is_empty());
+    }
+
+    #[test]
+    fn plain_dirs_merge_children() {
+        let mut high = with_child(
+            entry("app", NodeFileType::Directory, "a"),
+            entry("Foo", NodeFileType::Directory, "a"),
+        );
+        let low = with_child(
+            entry("app", NodeFileType::Directory, "b"),
+            entry("Bar", NodeFileType::Directory, "b"),
+        );
+
+        merge_nodes(&mut high, low);
+
+        assert!(high.children.contains_key("Foo"));
+        assert!(high.children.contains_key("Bar"));
+    }
+
+    #[test]
+    fn replace_dir_hides_lower_children() {
+        let mut replaced = entry("app", NodeFileType::Directory, "a");
+        replaced.replace = true;
+
+        let mut high = with_child(replaced, entry("Foo", NodeFileType::Directory, "a"));
+        let low = with_child(
+            entry("app", NodeFileType::Directory, "b"),
+            entry("Bar", NodeFileType::Directory, "b"),
+        );
+
+        merge_nodes(&mut high, low);
+
+        assert!(high.replace);
+        assert!(high.children.contains_key("Foo"));
+        assert!(!high.children.contains_key("Bar"));
+    }
+
+    #[test]
+    fn coerced_partition_dir_keeps_its_type() {
+        // process_module turns a module's system/vendor symlink into an unclaimed dir once the
+        // module also ships /vendor; a lower module's symlink must not undo that
+        let mut high = with_child(
+            Node::new_root("vendor"),
+            entry("lib", NodeFileType::Directory, "a"),
+        );
+        let low = entry("vendor", NodeFileType::Symlink, "b");
+
+        merge_nodes(&mut high, low);
+
+        assert_eq!(high.file_type, NodeFileType::Directory);
+        assert!(high.module_path.is_none());
+        assert!(high.children.contains_key("lib"));
+    }
+
+    #[test]
+    fn empty_unclaimed_dir_takes_lower_symlink() {
+        let mut high = Node::new_root("vendor");
+        let low = entry("vendor", NodeFileType::Symlink, "b");
+
+        merge_nodes(&mut high, low);
+
+        assert_eq!(high.file_type, NodeFileType::Symlink);
+        assert_eq!(
+            source_of(&high),
+            Some(Path::new("/data/adb/modules/b/vendor"))
+        );
+    }
+
+    #[test]
+    fn claimed_symlink_ignores_lower_partition_dir() {
+        let mut high = entry("vendor", NodeFileType::Symlink, "a");
+        let low = with_child(
+            Node::new_root("vendor"),
+            entry("lib", NodeFileType::Directory, "b"),
+        );
+
+        merge_nodes(&mut high, low);
+
+        assert_eq!(high.file_type, NodeFileType::Symlink);
+        assert!(high.children.is_empty());
+    }
+
+    #[test]
+    fn partition_symlink_outside_module_is_rejected() {
+        let base = ScratchDir::new("magic-escape");
+        let module = base.join("escaper");
+        let outside = base.join("outside");
+
+        touch(&module.join("system/bin/tool"));
+        touch(&outside.join("lib/evil.so"));
+        symlink(&outside, module.join("vendor")).unwrap();
+
+        let (_, system) = process_module(&module, &[], None).unwrap();
+
+        assert!(system.children.contains_key("bin"));
+        assert!(!system.children.contains_key("vendor"));
+    }
+
+    #[test]
+    fn partition_root_replace_marker_is_ignored() {
+        let base = ScratchDir::new("magic-root-replace");
+        let module = base.join("replacer");
+
+        touch(&module.join("system").join(REPLACE_DIR_FILE_NAME));
+        touch(&module.join("system/bin/tool"));
+
+        let root = collect_module_files(
+            std::slice::from_ref(&module),
+            &[],
+            &HashMap::new(),
+            &HashMap::new(),
+        )
+        .unwrap()
+        .unwrap();
+
+        let system = &root.children["system"];
+        assert!(!system.replace);
+        assert!(system.module_path.is_none());
+        assert!(system.children.contains_key("bin"));
+        assert!(!system.children.contains_key(REPLACE_DIR_FILE_NAME));
+    }
+}
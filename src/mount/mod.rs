@@ -4,3 +4,4 @@
 pub mod magic;
 pub mod node;
 pub mod overlay;
+pub mod script;
@@ -8,9 +8,12 @@ use std::{
     path::{Component, PathBuf},
 };
 
-use crate::core::modules::ModuleFile;
+use serde::Serialize;
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Copy)]
+use crate::{core::modules::ModuleFile, defs};
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum NodeFileType {
     RegularFile,
     Directory,
@@ -41,7 +44,7 @@ impl fmt::Display for NodeFileType {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct Node {
     pub name: String,
     pub file_type: NodeFileType,
@@ -131,13 +134,20 @@ impl fmt::Debug for Node {
 
 impl Node {
     pub fn new_root<S>(name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self::new_entry(name, NodeFileType::Directory, None)
+    }
+
+    pub fn new_entry<S>(name: S, file_type: NodeFileType, module_path: Option<PathBuf>) -> Self
     where
         S: Into<String>,
     {
         Self {
             name: name.into(),
-            file_type: NodeFileType::Directory,
-            module_path: None,
+            file_type,
+            module_path,
             children: HashMap::new(),
             replace: false,
             skip: false,
@@ -145,11 +155,34 @@ impl Node {
     }
 
     pub fn collect_module_files(&mut self, root: &PathBuf) -> anyhow::Result<()> {
-        for entry in walkdir::WalkDir::new(root)
-            .min_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
+        if root.join(defs::REPLACE_DIR_FILE_NAME).exists() {
+            log::warn!(
+                "Ignoring replace marker on partition root {}",
+                root.display()
+            );
+        }
+
+        let mut walker = walkdir::WalkDir::new(root).min_depth(1).into_iter();
+
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+
+            if entry.depth() > defs::MAX_TREE_DEPTH {
+                log::warn!(
+                    "Skipping {}: module tree deeper than {} levels",
+                    entry.path().display(),
+                    defs::MAX_TREE_DEPTH
+                );
+
+                if entry.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+
+                continue;
+            }
+
             let path = entry.path();
 
             let relative_path = path.strip_prefix(root)?;
@@ -186,14 +219,7 @@ impl Node {
                 let node = current_node
                     .children
                     .entry(name.clone())
-                    .or_insert_with(|| Node {
-                        name: name.clone(),
-                        file_type,
-                        module_path: None,
-                        children: HashMap::new(),
-                        replace: false,
-                        skip: false,
-                    });
+                    .or_insert_with(|| Node::new_entry(name.clone(), file_type, None));
 
                 if !module_file.is_whiteout {
                     node.module_path = Some(module_file.real_path.clone());
@@ -210,14 +236,7 @@ impl Node {
                 current_node = current_node
                     .children
                     .entry(name.clone())
-                    .or_insert_with(|| Node {
-                        name,
-                        file_type: NodeFileType::Directory,
-                        module_path: None,
-                        children: HashMap::new(),
-                        replace: false,
-                        skip: false,
-                    });
+                    .or_insert_with(|| Node::new_root(name));
             }
         }
     }
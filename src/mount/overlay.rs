@@ -4,6 +4,7 @@
 use std::{
     ffi::CString,
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
 use anyhow::{Context, Result};
@@ -11,18 +12,152 @@ use log::{info, warn};
 use procfs::process::Process;
 use rustix::{fd::AsFd, fs::CWD, mount::*};
 
-use crate::defs::KSU_OVERLAY_SOURCE;
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::try_umount::send_unmountable;
+use crate::{
+    defs,
+    error::MountError,
+    mount::script::{MountScript, quote},
+};
+
+// Filesystems that cannot store trusted.* xattrs. Overlay layers on them need `userxattr`
+// (Linux 5.11+) so opaque dirs and redirects are read from user.overlay.* instead, otherwise
+// deletions and replaced dirs are silently ignored
+const USERXATTR_FS_TYPES: [&str; 4] = ["fuse", "fuseblk", "sdcardfs", "virtiofs"];
+
+// OVL_MAX_STACK in the kernel, and the legacy mount(2) option string is copied into one page
+const OVERLAY_MAX_LAYERS: usize = 500;
+const OVERLAY_MAX_DATA_LEN: usize = 4095;
+
+static OVERLAY_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+fn kernel_supports_userxattr() -> bool {
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .and_then(|release| {
+            let mut parts = release.trim().split(['.', '-']);
+            let major = parts.next()?.parse::<u32>().ok()?;
+            let minor = parts.next()?.parse::<u32>().ok()?;
+            Some((major, minor) >= (5, 11))
+        })
+        .unwrap_or(false)
+}
+
+pub fn needs_userxattr(lower_dirs: &[String]) -> bool {
+    if !kernel_supports_userxattr() {
+        return false;
+    }
+
+    let Ok(mounts) = Process::myself().and_then(|p| p.mountinfo()) else {
+        return false;
+    };
+
+    lower_dirs.iter().any(|dir| {
+        mounts
+            .0
+            .iter()
+            .filter(|m| Path::new(dir).starts_with(&m.mount_point))
+            .max_by_key(|m| m.mount_point.as_os_str().len())
+            .is_some_and(|m| USERXATTR_FS_TYPES.contains(&m.fs_type.as_str()))
+    })
+}
+
+fn probe_overlay() -> Result<()> {
+    let probe_dir = Path::new(defs::RUN_DIR).join("overlay_probe");
+    let lower = [probe_dir.join("lower0"), probe_dir.join("lower1")];
+    let merged = probe_dir.join("merged");
+    for dir in lower.iter().chain(std::iter::once(&merged)) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    // Two lowers and no upper is the smallest overlay every kernel accepts
+    let data = CString::new(format!(
+        "lowerdir={}:{}",
+        lower[0].display(),
+        lower[1].display()
+    ))?;
+    let result = mount(
+        "overlay",
+        &merged,
+        "overlay",
+        MountFlags::RDONLY,
+        data.as_c_str(),
+    );
+    if result.is_ok() {
+        let _ = unmount(&merged, UnmountFlags::DETACH);
+    }
+    let _ = std::fs::remove_dir_all(&probe_dir);
+    result.with_context(|| "trial overlay mount failed")
+}
+
+pub fn overlay_available() -> bool {
+    *OVERLAY_AVAILABLE.get_or_init(|| match probe_overlay() {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("overlayfs is unavailable: {e:#}");
+            false
+        }
+    })
+}
+
+// `extra` holds every option appended after lowerdir (upperdir/workdir, userxattr, user
+// options), as they all share the one mount data page
+pub fn check_overlay_limits(lowerdir_config: &str, layers: usize, extra: &[String]) -> Result<()> {
+    if layers > OVERLAY_MAX_LAYERS {
+        return Err(MountError::OverlayUnsupported(format!(
+            "{layers} lower layers exceed the kernel limit of {OVERLAY_MAX_LAYERS}"
+        ))
+        .into());
+    }
+    let len = "lowerdir=".len()
+        + lowerdir_config.len()
+        + extra.iter().map(|option| option.len() + 1).sum::<usize>();
+    if len > OVERLAY_MAX_DATA_LEN {
+        return Err(MountError::OverlayUnsupported(format!(
+            "mount data is {len} bytes, over the {OVERLAY_MAX_DATA_LEN} byte limit"
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+pub fn overlay_extra_options(
+    upperdir: Option<&str>,
+    workdir: Option<&str>,
+    userxattr: bool,
+    options: &[String],
+) -> Vec<String> {
+    let mut extra = Vec::new();
+    if let (Some(upper), Some(work)) = (upperdir, workdir) {
+        extra.push(format!("upperdir={upper}"));
+        extra.push(format!("workdir={work}"));
+    }
+    if userxattr {
+        extra.push("userxattr".to_string());
+    }
+    extra.extend(options.iter().cloned());
+    extra
+}
+
+// Everything about an overlay besides its layers and where it goes
+pub struct OverlayParams<'a> {
+    pub upperdir: Option<PathBuf>,
+    pub workdir: Option<PathBuf>,
+    pub userxattr: Option<bool>,
+    pub options: &'a [String],
+    pub mount_source: &'a str,
+}
 
 pub fn mount_overlayfs(
     lower_dirs: &[String],
     lowest: &str,
-    upperdir: Option<PathBuf>,
-    workdir: Option<PathBuf>,
     dest: impl AsRef<Path>,
+    params: &OverlayParams,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
 ) -> Result<()> {
+    let (upperdir, workdir) = (&params.upperdir, &params.workdir);
+    let (options, mount_source) = (params.options, params.mount_source);
     let lowerdir_config = lower_dirs
         .iter()
         .map(|s| s.as_ref())
@@ -38,11 +173,27 @@ pub fn mount_overlayfs(
     );
 
     let upperdir_s = upperdir
+        .as_ref()
         .filter(|up| up.exists())
         .map(|e| e.display().to_string());
     let workdir_s = workdir
+        .as_ref()
         .filter(|wd| wd.exists())
         .map(|e| e.display().to_string());
+    let userxattr = params
+        .userxattr
+        .unwrap_or_else(|| needs_userxattr(lower_dirs));
+    let extra = overlay_extra_options(
+        upperdir_s.as_deref(),
+        workdir_s.as_deref(),
+        userxattr,
+        options,
+    );
+    check_overlay_limits(&lowerdir_config, lower_dirs.len() + 1, &extra)
+        .with_context(|| format!("overlay on {:?} is too large", dest.as_ref()))?;
+    if userxattr {
+        info!("overlay on {:?} uses userxattr", dest.as_ref());
+    }
 
     // Try New API (fsopen)
     let result = (|| {
@@ -53,7 +204,19 @@ pub fn mount_overlayfs(
             fsconfig_set_string(fs, "upperdir", upper)?;
             fsconfig_set_string(fs, "workdir", work)?;
         }
-        fsconfig_set_string(fs, "source", KSU_OVERLAY_SOURCE)?;
+        if userxattr {
+            fsconfig_set_flag(fs, "userxattr")?;
+        }
+        for option in options {
+            let applied = match option.split_once('=') {
+                Some((key, value)) => fsconfig_set_string(fs, key, value),
+                None => fsconfig_set_flag(fs, option),
+            };
+            if let Err(e) = applied {
+                warn!("overlay option {option} rejected by the kernel: {e}, ignoring it");
+            }
+        }
+        fsconfig_set_string(fs, "source", mount_source)?;
         fsconfig_create(fs)?;
         let mount = fsmount(fs, FsMountFlags::FSMOUNT_CLOEXEC, MountAttrFlags::empty())?;
         move_mount(
@@ -68,18 +231,40 @@ pub fn mount_overlayfs(
     // Fallback to Old API (mount)
     if let Err(e) = result {
         warn!("fsopen mount failed: {e:#}, fallback to mount");
-        let mut data = format!("lowerdir={lowerdir_config}");
-        if let (Some(upper), Some(work)) = (upperdir_s, workdir_s) {
-            data = format!("{data},upperdir={upper},workdir={work}");
+        let mount_legacy = |extra: &[String]| -> Result<()> {
+            let mut data = format!("lowerdir={lowerdir_config}");
+            if let (Some(upper), Some(work)) = (&upperdir_s, &workdir_s) {
+                data = format!("{data},upperdir={upper},workdir={work}");
+            }
+            if userxattr {
+                data = format!("{data},userxattr");
+            }
+            for option in extra {
+                data = format!("{data},{option}");
+            }
+            let data_c = CString::new(data)?;
+            mount(
+                mount_source,
+                dest.as_ref(),
+                "overlay",
+                MountFlags::empty(),
+                data_c.as_c_str(),
+            )
+            .map_err(MountError::from_overlay_errno)
+        };
+        // The legacy API rejects the data string as a whole, so the extra options go together
+        let mut result = mount_legacy(options);
+        let rejected = result.as_ref().is_err_and(|e| {
+            e.downcast_ref::<rustix::io::Errno>() == Some(&rustix::io::Errno::INVAL)
+        });
+        if rejected && !options.is_empty() {
+            warn!(
+                "overlay options {} rejected by the kernel, retrying without them",
+                options.join(",")
+            );
+            result = mount_legacy(&[]);
         }
-        let data_c = CString::new(data)?;
-        mount(
-            KSU_OVERLAY_SOURCE,
-            dest.as_ref(),
-            "overlay",
-            MountFlags::empty(),
-            data_c.as_c_str(),
-        )?;
+        result?;
     }
 
     #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -123,11 +308,94 @@ pub fn bind_mount(
     Ok(())
 }
 
+fn child_mounts(root: &str) -> Result<Vec<String>> {
+    let mounts = Process::myself()?
+        .mountinfo()
+        .with_context(|| "get mountinfo")?;
+    let mut mount_seq = mounts
+        .0
+        .iter()
+        .filter(|m| {
+            m.mount_point.starts_with(root) && !Path::new(&root).starts_with(&m.mount_point)
+        })
+        .filter_map(|m| m.mount_point.to_str().map(str::to_string))
+        .collect::<Vec<_>>();
+    mount_seq.sort();
+    mount_seq.dedup();
+    Ok(mount_seq)
+}
+
+pub fn render_overlay(
+    script: &mut MountScript,
+    root: &str,
+    module_roots: &[String],
+    params: &OverlayParams,
+) -> Result<()> {
+    if !Path::new(root).exists() {
+        script.comment(&format!("{root} does not exist, skipped"));
+        return Ok(());
+    }
+
+    let mount_seq = child_mounts(root)?;
+
+    let mut data = format!(
+        "lowerdir={}",
+        module_roots
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(root))
+            .collect::<Vec<_>>()
+            .join(":")
+    );
+    if let (Some(upper), Some(work)) = (
+        params.upperdir.as_ref().filter(|up| up.exists()),
+        params.workdir.as_ref().filter(|wd| wd.exists()),
+    ) {
+        data = format!(
+            "{data},upperdir={},workdir={}",
+            upper.display(),
+            work.display()
+        );
+    }
+    if params
+        .userxattr
+        .unwrap_or_else(|| needs_userxattr(module_roots))
+    {
+        data = format!("{data},userxattr");
+    }
+    for option in params.options {
+        data = format!("{data},{option}");
+    }
+
+    // The stock tree stays reachable through the cwd, exactly like mount_overlay does
+    script.push(format!("cd {}", quote(root)));
+    script.push(format!(
+        "mount -t overlay -o {} {} {}",
+        quote(&data),
+        quote(params.mount_source),
+        quote(root)
+    ));
+
+    for mount_point in &mount_seq {
+        let relative = mount_point.replacen(root, "", 1);
+        if !Path::new(mount_point).exists() {
+            continue;
+        }
+        script.push(format!(
+            "mount --rbind {} {}",
+            quote(format!(".{relative}")),
+            quote(mount_point)
+        ));
+    }
+
+    script.push("cd /".to_string());
+    Ok(())
+}
+
 pub fn mount_overlay(
     root: &str,
     module_roots: &[String],
-    workdir: Option<PathBuf>,
-    upperdir: Option<PathBuf>,
+    params: &OverlayParams,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
 ) -> Result<()> {
     info!("mount overlay for {root}");
@@ -142,36 +410,21 @@ pub fn mount_overlay(
     let stock_root = ".";
 
     // collect child mounts before mounting the root
-    let mounts = Process::myself()?
-        .mountinfo()
-        .with_context(|| "get mountinfo")?;
-    let mut mount_seq = mounts
-        .0
-        .iter()
-        .filter(|m| {
-            m.mount_point.starts_with(root) && !Path::new(&root).starts_with(&m.mount_point)
-        })
-        .map(|m| m.mount_point.to_str())
-        .collect::<Vec<_>>();
-    mount_seq.sort();
-    mount_seq.dedup();
+    let mount_seq = child_mounts(root)?;
 
     mount_overlayfs(
         module_roots,
         root,
-        upperdir,
-        workdir,
         root,
+        params,
         #[cfg(any(target_os = "linux", target_os = "android"))]
         disable_umount,
     )
     .with_context(|| "mount overlayfs for root failed")?;
 
     // Handle child mounts (nested mounts)
+    let mut restored: Vec<&str> = Vec::new();
     for mount_point in mount_seq.iter() {
-        let Some(mount_point) = mount_point else {
-            continue;
-        };
         let relative = mount_point.replacen(root, "", 1);
         let stock_root_child: String = format!("{stock_root}{relative}");
         if !Path::new(&stock_root_child).exists() {
@@ -185,13 +438,26 @@ pub fn mount_overlay(
             #[cfg(any(target_os = "linux", target_os = "android"))]
             disable_umount,
         ) {
-            warn!("failed to restore child mount {mount_point}: {e:#}");
+            warn!(
+                "failed to restore child mount {mount_point}: {e:#}, reverting overlay on {root}"
+            );
+
+            // A hidden child mount (apex, vendor, ...) is worse than no overlay at all, so
+            // unwind innermost first and let the caller fall back to magic mount
+            for child in restored.iter().rev() {
+                if let Err(e) = umount_dir(child) {
+                    warn!("{e:#}");
+                }
+            }
+            umount_dir(root)?;
+
+            return Err(e).with_context(|| format!("failed to restore child mount {mount_point}"));
         }
+        restored.push(mount_point);
     }
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn umount_dir(src: impl AsRef<Path>) -> Result<()> {
     unmount(src.as_ref(), UnmountFlags::DETACH)
         .with_context(|| format!("Failed to umount {}", src.as_ref().display()))?;
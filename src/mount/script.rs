@@ -0,0 +1,39 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+
+pub struct MountScript {
+    lines: Vec<String>,
+}
+
+impl MountScript {
+    pub fn new() -> Self {
+        Self {
+            lines: vec!["#!/system/bin/sh".to_string(), "set -e".to_string()],
+        }
+    }
+
+    pub fn comment(&mut self, text: &str) {
+        self.lines.push(String::new());
+        self.lines.push(format!("# {}", text));
+    }
+
+    pub fn push(&mut self, command: String) {
+        self.lines.push(command);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = self.lines.join("\n");
+        out.push('\n');
+        out
+    }
+}
+
+pub fn quote<S: AsRef<str>>(value: S) -> String {
+    format!("'{}'", value.as_ref().replace('\'', r"'\''"))
+}
+
+pub fn quote_path<P: AsRef<Path>>(path: P) -> String {
+    quote(path.as_ref().to_string_lossy())
+}
@@ -4,7 +4,7 @@
 use std::{
     ffi::CString,
     os::fd::RawFd,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{LazyLock, Mutex, OnceLock},
 };
 
@@ -12,11 +12,21 @@ use anyhow::{Context, Result, bail};
 use ksu::TryUmount;
 use nix::ioctl_write_ptr_bad;
 
+use crate::{
+    conf::config::{Config, RootBackend},
+    defs,
+    error::MountError,
+    utils,
+};
+
 const KSU_INSTALL_MAGIC1: u32 = 0xDEADBEEF;
 const KSU_INSTALL_MAGIC2: u32 = 0xCAFEBABE;
 const KSU_IOCTL_NUKE_EXT4_SYSFS: u32 = 0x40004b11;
 
 static DRIVER_FD: OnceLock<RawFd> = OnceLock::new();
+static BACKEND: OnceLock<RootBackend> = OnceLock::new();
+static UMOUNT_DISABLED: OnceLock<bool> = OnceLock::new();
+static MODULE_DIR: OnceLock<PathBuf> = OnceLock::new();
 pub static TMPFS: OnceLock<String> = OnceLock::new();
 pub static LIST: LazyLock<Mutex<TryUmount>> = LazyLock::new(|| Mutex::new(TryUmount::new()));
 
@@ -47,18 +57,76 @@ fn grab_fd() -> i32 {
     fd
 }
 
+pub fn init(config: &Config) {
+    let _ = BACKEND.set(config.root_backend);
+    let _ = MODULE_DIR.set(config.moduledir.clone());
+    let _ = UMOUNT_DISABLED.set(config.umount_disabled(zygisksu_enforce()));
+}
+
+pub fn zygisksu_enforce() -> bool {
+    let moduledir = MODULE_DIR
+        .get()
+        .map(PathBuf::as_path)
+        .unwrap_or(Path::new(defs::MODULES_DIR));
+
+    utils::check_zygisksu_enforce_status(moduledir)
+}
+
+fn is_umount_disabled() -> bool {
+    UMOUNT_DISABLED.get().copied().unwrap_or(false)
+}
+
+fn driver_fd() -> RawFd {
+    *DRIVER_FD.get_or_init(|| {
+        let backend = BACKEND.get().copied().unwrap_or_default();
+
+        // The KSU ioctl interface is only probed on KSU, other backends never get a fd
+        if backend != RootBackend::Ksu {
+            log::debug!("Root backend {:?}: KSU driver interface not used", backend);
+            return -1;
+        }
+
+        let fd = grab_fd();
+
+        if fd < 0 {
+            log::warn!("KSU driver fd unavailable, try-umount and nuke are disabled");
+        }
+
+        fd
+    })
+}
+
+pub fn is_driver_available() -> bool {
+    driver_fd() >= 0
+}
+
 pub fn send_unmountable<P>(target: P) -> Result<()>
 where
     P: AsRef<Path>,
 {
+    if is_umount_disabled() || !is_driver_available() {
+        return Ok(());
+    }
+
     LIST.lock().unwrap().add(target);
     Ok(())
 }
 
 pub fn commit() -> Result<()> {
+    if is_umount_disabled() {
+        log::debug!("try_umount commit skipped: umount disabled");
+        return Ok(());
+    }
+
+    if !is_driver_available() {
+        log::debug!("try_umount commit skipped: KSU driver unavailable");
+        return Ok(());
+    }
+
     let mut list = LIST.lock().unwrap();
     list.flags(2);
     list.umount()?;
+    log::debug!("try_umount list committed");
     Ok(())
 }
 
@@ -69,10 +137,10 @@ pub fn ksu_nuke_sysfs(target: &str) -> Result<()> {
         arg: c_path.as_ptr() as u64,
     };
 
-    let fd = *DRIVER_FD.get_or_init(grab_fd);
+    let fd = driver_fd();
 
     if fd < 0 {
-        bail!("KSU driver not available");
+        return Err(MountError::KsuDriverMissing.into());
     }
 
     unsafe {
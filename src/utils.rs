@@ -5,7 +5,7 @@ use std::{
     ffi::CString,
     fmt as std_fmt,
     fs::{self, File, create_dir_all, remove_dir_all, remove_file, write},
-    io::Write,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
     os::unix::{
         ffi::OsStrExt,
         fs::{FileTypeExt, MetadataExt, PermissionsExt, symlink},
@@ -22,11 +22,13 @@ use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lsetxattr};
 use procfs::process::Process;
 use regex_lite::Regex;
 use rustix::{
-    fs::ioctl_ficlone,
+    fs::{Access, FlockOperation, StatVfsMountFlags, access, flock, ioctl_ficlone, statvfs},
+    io::Errno,
     mount::{MountFlags, mount},
 };
 use tracing::{Event, Subscriber};
 use tracing_appender::non_blocking::WorkerGuard;
+use tracing_log::NormalizeEvent;
 use tracing_subscriber::{
     EnvFilter,
     fmt::{self, FmtContext, FormatEvent, FormatFields},
@@ -35,10 +37,14 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
 };
 
-use crate::defs::{self, TMPFS_CANDIDATES};
+use crate::{
+    conf::config::{LogFormat, MountRetryConfig},
+    defs::{self, TMPFS_CANDIDATES},
+};
 
 const SELINUX_XATTR: &str = "security.selinux";
 const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+const OVERLAY_USER_OPAQUE_XATTR: &str = "user.overlay.opaque";
 const CONTEXT_SYSTEM: &str = "u:object_r:system_file:s0";
 const CONTEXT_VENDOR: &str = "u:object_r:vendor_file:s0";
 const OVERLAY_TEST_XATTR: &str = "trusted.overlay.test";
@@ -47,6 +53,7 @@ const OVERLAY_TEST_XATTR: &str = "trusted.overlay.test";
 const XATTR_TEST_FILE: &str = ".xattr_test";
 
 static MODULE_ID_REGEX: OnceLock<Regex> = OnceLock::new();
+static SELF_MODULE_ID: OnceLock<Option<String>> = OnceLock::new();
 
 struct SimpleFormatter;
 
@@ -68,7 +75,146 @@ where
     }
 }
 
-pub fn init_logging(verbose: bool, log_path: &Path) -> Result<WorkerGuard> {
+struct JsonFormatter;
+
+#[derive(Default)]
+struct JsonFieldVisitor {
+    message: String,
+}
+
+impl tracing::field::Visit for JsonFieldVisitor {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std_fmt::Debug) {
+        let name = field.name();
+
+        if name == "message" {
+            self.message = format!("{:?}", value);
+        } else if !name.starts_with("log.") {
+            self.message.push_str(&format!(" {}={:?}", name, value));
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormatter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: fmt::format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std_fmt::Result {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut visitor = JsonFieldVisitor::default();
+        event.record(&mut visitor);
+
+        writeln!(
+            writer,
+            "{}",
+            json_log_line(
+                metadata.level().as_str(),
+                metadata.target(),
+                &visitor.message
+            )
+        )
+    }
+}
+
+pub fn json_log_line(level: &str, target: &str, message: &str) -> String {
+    serde_json::json!({
+        "timestamp": chrono::Local::now().to_rfc3339(),
+        "level": level,
+        "target": target,
+        "message": message,
+    })
+    .to_string()
+}
+
+const LOG_ROTATE_KEEP: usize = 2;
+const LOG_TAIL_MAX_BYTES: u64 = 1024 * 1024;
+
+fn rotated_log_path(log_path: &Path, index: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", log_path.display(), index))
+}
+
+fn rotate_log(log_path: &Path, max_size_kb: u64) {
+    if max_size_kb == 0 {
+        return;
+    }
+
+    let Ok(metadata) = fs::metadata(log_path) else {
+        return;
+    };
+
+    if metadata.len() <= max_size_kb * 1024 {
+        return;
+    }
+
+    for index in (1..LOG_ROTATE_KEEP).rev() {
+        let from = rotated_log_path(log_path, index);
+        if from.exists() {
+            let _ = fs::rename(&from, rotated_log_path(log_path, index + 1));
+        }
+    }
+
+    // On failure we simply keep appending to the oversized file
+    if let Err(e) = fs::rename(log_path, rotated_log_path(log_path, 1)) {
+        eprintln!("Log rotation failed for {}: {}", log_path.display(), e);
+    }
+}
+
+fn tail_lines(path: &Path) -> Result<Vec<String>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let start = file.metadata()?.len().saturating_sub(LOG_TAIL_MAX_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    file.take(LOG_TAIL_MAX_BYTES).read_to_end(&mut buf)?;
+    let mut lines: Vec<String> = String::from_utf8_lossy(&buf)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    // The first line is cut in half when the read starts mid-file
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    Ok(lines)
+}
+
+pub fn read_log_tail(log_path: &Path, count: usize) -> Result<Vec<String>> {
+    let mut lines =
+        tail_lines(log_path).with_context(|| format!("Failed to read {}", log_path.display()))?;
+    if lines.len() < count {
+        let rotated = rotated_log_path(log_path, 1);
+        let mut older = tail_lines(&rotated)
+            .with_context(|| format!("Failed to read {}", rotated.display()))?;
+        older.append(&mut lines);
+        lines = older;
+    }
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines.split_off(skip))
+}
+
+pub fn init_logging(
+    verbose: bool,
+    log_path: &Path,
+    max_size_kb: u64,
+    log_format: LogFormat,
+) -> Result<WorkerGuard> {
     let parent = log_path
         .parent()
         .ok_or_else(|| anyhow::anyhow!("Invalid log path parent"))?;
@@ -77,6 +223,7 @@ pub fn init_logging(verbose: bool, log_path: &Path) -> Result<WorkerGuard> {
         .ok_or_else(|| anyhow::anyhow!("Invalid log filename"))?;
 
     create_dir_all(parent)?;
+    rotate_log(log_path, max_size_kb);
 
     let file_appender = tracing_appender::rolling::never(parent, file_name);
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
@@ -86,14 +233,26 @@ pub fn init_logging(verbose: bool, log_path: &Path) -> Result<WorkerGuard> {
         EnvFilter::new("info")
     };
 
-    let file_layer = fmt::layer()
-        .with_ansi(false)
-        .with_writer(non_blocking)
-        .event_format(SimpleFormatter);
+    let json = log_format == LogFormat::Json;
+
+    let text_layer = (!json).then(|| {
+        fmt::layer()
+            .with_ansi(false)
+            .with_writer(non_blocking.clone())
+            .event_format(SimpleFormatter)
+    });
+
+    let json_layer = json.then(|| {
+        fmt::layer()
+            .with_ansi(false)
+            .with_writer(non_blocking)
+            .event_format(JsonFormatter)
+    });
 
     tracing_subscriber::registry()
         .with(filter)
-        .with(file_layer)
+        .with(text_layer)
+        .with(json_layer)
         .init();
     tracing_log::LogTracer::init().ok();
 
@@ -151,6 +310,74 @@ pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, content: C) -> Resu
     Ok(())
 }
 
+// The lock is released when the returned file is dropped or the process exits, so a crashed
+// daemon never leaves a stale lock behind. None means another instance holds it
+pub fn acquire_daemon_lock() -> Result<Option<File>> {
+    ensure_dir_exists(defs::RUN_DIR)?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(defs::DAEMON_LOCK_FILE)
+        .with_context(|| format!("Failed to open lock file {}", defs::DAEMON_LOCK_FILE))?;
+    match flock(&file, FlockOperation::NonBlockingLockExclusive) {
+        Ok(()) => Ok(Some(file)),
+        Err(Errno::WOULDBLOCK) => Ok(None),
+        Err(e) => Err(e).context("Failed to lock the daemon lock file"),
+    }
+}
+
+pub fn prop_line_key(line: &str) -> Option<&str> {
+    if line.trim_start().starts_with('#') {
+        return None;
+    }
+    line.split_once('=').map(|(k, _)| k.trim())
+}
+
+pub fn read_prop_line<P: AsRef<Path>>(path: P, key: &str) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read prop file {}", path.display()))?;
+    Ok(content.lines().find_map(|line| {
+        let line = line.trim_end_matches('\r');
+        (prop_line_key(line) == Some(key))
+            .then(|| line.split_once('=').map(|(_, v)| v.trim().to_string()))
+            .flatten()
+    }))
+}
+
+pub fn update_prop_line<P: AsRef<Path>>(path: P, key: &str, value: &str) -> Result<()> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        bail!("Prop file {} does not exist", path.display());
+    }
+    if key.is_empty() || key.trim() != key || key.contains(['=', '#', '\n', '\r']) {
+        bail!("Invalid prop key '{key}'");
+    }
+    if value.contains(['\n', '\r']) {
+        bail!("Value for prop key '{key}' must be a single line");
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read prop file {}", path.display()))?;
+    let mut found = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            if prop_line_key(line) == Some(key) {
+                found = true;
+                format!("{key}={value}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        lines.push(format!("{key}={value}"));
+    }
+    atomic_write(path, format!("{}\n", lines.join("\n")))
+        .with_context(|| format!("Failed to write prop file {}", path.display()))
+}
+
 pub fn validate_module_id(module_id: &str) -> Result<()> {
     let re = MODULE_ID_REGEX
         .get_or_init(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9._-]+$").expect("Invalid Regex pattern"));
@@ -161,7 +388,36 @@ pub fn validate_module_id(module_id: &str) -> Result<()> {
     }
 }
 
-pub fn check_zygisksu_enforce_status() -> bool {
+pub fn self_module_id() -> Option<&'static str> {
+    SELF_MODULE_ID
+        .get_or_init(|| {
+            let exe = std::env::current_exe().ok()?;
+            let module_dir = exe.parent()?;
+
+            // Only trust the install dir when it actually looks like a module
+            if !module_dir.join("module.prop").exists() {
+                return None;
+            }
+
+            module_dir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+        })
+        .as_deref()
+}
+
+// The enforce flag outlives an uninstalled or disabled ZygiskSU, so it only counts while the
+// module is active in the module dir
+pub fn check_zygisksu_enforce_status(moduledir: &Path) -> bool {
+    let module = moduledir.join("zygisksu");
+
+    if !module.is_dir()
+        || module.join(defs::DISABLE_FILE_NAME).exists()
+        || module.join(defs::REMOVE_FILE_NAME).exists()
+    {
+        return false;
+    }
+
     std::fs::read_to_string("/data/adb/zygisksu/denylist_enforce")
         .map(|s| s.trim() != "0")
         .unwrap_or(false)
@@ -229,6 +485,35 @@ pub fn lsetfilecon<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
     Ok(())
 }
 
+// An overlay mounted with userxattr only reads user.overlay.*, and the filesystems that need
+// it often cannot store trusted.* at all, so there only the user namespace write must succeed
+pub fn set_overlay_opaque<P: AsRef<Path>>(path: P, userxattr: bool) -> Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let trusted = lsetxattr(
+            path.as_ref(),
+            OVERLAY_OPAQUE_XATTR,
+            b"y",
+            XattrFlags::empty(),
+        );
+        if userxattr {
+            lsetxattr(
+                path.as_ref(),
+                OVERLAY_USER_OPAQUE_XATTR,
+                b"y",
+                XattrFlags::empty(),
+            )
+            .with_context(|| format!("Failed to mark {} opaque", path.as_ref().display()))?;
+        } else {
+            trusted
+                .with_context(|| format!("Failed to mark {} opaque", path.as_ref().display()))?;
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = (path, userxattr);
+    Ok(())
+}
+
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn lgetfilecon<P: AsRef<Path>>(path: P) -> Result<String> {
     let con = extattr::lgetxattr(path.as_ref(), SELINUX_XATTR).with_context(|| {
@@ -276,6 +561,34 @@ pub fn camouflage_process(name: &str) -> Result<()> {
     Ok(())
 }
 
+pub fn enter_private_mount_namespace() -> Result<()> {
+    unsafe {
+        if libc::unshare(libc::CLONE_NEWNS) != 0 {
+            bail!(
+                "unshare(CLONE_NEWNS) failed: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        // Without this the new namespace still shares peer groups with init and every
+        // mount below would propagate right back out
+        let root = CString::new("/")?;
+        if libc::mount(
+            std::ptr::null(),
+            root.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        ) != 0
+        {
+            bail!(
+                "failed to make / recursively private: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
 pub fn random_kworker_name() -> String {
     use std::{
         collections::hash_map::DefaultHasher,
@@ -283,30 +596,62 @@ pub fn random_kworker_name() -> String {
     };
 
     let mut hasher = DefaultHasher::new();
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .hash(&mut hasher);
+    // Seeded by boot_id so every run within one boot picks the same name
+    match fs::read_to_string("/proc/sys/kernel/random/boot_id") {
+        Ok(boot_id) => boot_id.trim().hash(&mut hasher),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .hash(&mut hasher),
+    }
     let hash = hasher.finish();
     let x = hash % 16;
     let y = (hash >> 4) % 10;
     format!("kworker/u{}:{}", x, y)
 }
 
+// The xattr probes write through this so tests can stand in for a filesystem that
+// rejects some namespaces
+type XattrSetter = dyn Fn(&Path, &str, &[u8]) -> std::io::Result<()>;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    lsetxattr(path, name, value, XattrFlags::empty()).map_err(std::io::Error::from)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_xattr(_path: &Path, _name: &str, _value: &[u8]) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn probe_selinux_xattr(file: &Path, set: &XattrSetter) -> Result<()> {
+    // lsetfilecon swallows errors, so probe the label it writes directly; a tmpfs that
+    // only accepts user.* xattrs must not pass this check
+    set(file, SELINUX_XATTR, CONTEXT_SYSTEM.as_bytes())
+        .with_context(|| format!("{} not supported on {}", SELINUX_XATTR, file.display()))
+}
+
 #[allow(dead_code)]
 pub fn is_xattr_supported(path: &Path) -> bool {
+    xattr_supported_with(path, &set_xattr)
+}
+
+fn xattr_supported_with(path: &Path, set: &XattrSetter) -> bool {
     let test_file = path.join(XATTR_TEST_FILE);
     if let Err(e) = write(&test_file, b"test") {
         log::debug!("XATTR Check: Failed to create test file: {}", e);
         return false;
     }
-    let result = lsetfilecon(&test_file, "u:object_r:system_file:s0");
-    let supported = result.is_ok();
+    let supported = probe_selinux_xattr(&test_file, set).is_ok();
     let _ = remove_file(test_file);
     supported
 }
 
 pub fn is_overlay_xattr_supported(path: &Path) -> Result<()> {
+    overlay_xattr_supported_with(path, &set_xattr)
+}
+
+fn overlay_xattr_supported_with(path: &Path, set: &XattrSetter) -> Result<()> {
     let test_file = path.join(".overlay_xattr_test");
     if let Err(e) = write(&test_file, b"test") {
         log::debug!("XATTR Check: Failed to create test file: {}", e);
@@ -314,19 +659,34 @@ pub fn is_overlay_xattr_supported(path: &Path) -> Result<()> {
     }
 
     // Attempt to set a dummy xattr to test support
-    #[cfg(any(target_os = "linux", target_os = "android"))]
-    if let Err(e) = rustix::fs::setxattr(
-        &test_file,
-        OVERLAY_TEST_XATTR,
-        b"y",
-        rustix::fs::XattrFlags::empty(),
-    ) {
+    if let Err(e) = set(&test_file, OVERLAY_TEST_XATTR, b"y") {
         log::debug!("Overlay XATTR test failed: {}", e);
         // Don't bail, just log
     }
 
+    let label_result = probe_selinux_xattr(&test_file, set);
+
     let _ = remove_file(test_file);
-    Ok(())
+    label_result
+}
+
+pub fn discover_partitions() -> Result<Vec<String>> {
+    let mounts = Process::myself()?
+        .mountinfo()
+        .context("Failed to read mountinfo")?;
+    let mut found: Vec<String> = mounts
+        .0
+        .iter()
+        .filter(|m| matches!(m.fs_type.as_str(), "ext4" | "erofs"))
+        .filter(|m| m.mount_options.contains_key("ro"))
+        .filter(|m| m.mount_point.parent() == Some(Path::new("/")))
+        .filter_map(|m| m.mount_point.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| !defs::BUILTIN_PARTITIONS.contains(&name.as_str()))
+        .collect();
+    found.sort();
+    found.dedup();
+    Ok(found)
 }
 
 pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
@@ -352,9 +712,31 @@ pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
     false
 }
 
+pub fn is_mount_readonly<P: AsRef<Path>>(path: P) -> Option<bool> {
+    let target = path.as_ref();
+    let mountinfo = Process::myself().ok()?.mountinfo().ok()?;
+
+    mountinfo
+        .0
+        .iter()
+        .rev()
+        .find(|m| m.mount_point.as_path() == target)
+        .map(|m| m.mount_options.contains_key("ro"))
+}
+
 pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
+    mount_tmpfs_sized(target, source, 0)
+}
+
+// size_mb = 0 keeps the kernel default of half the RAM
+pub fn mount_tmpfs_sized(target: &Path, source: &str, size_mb: u64) -> Result<()> {
     ensure_dir_exists(target)?;
-    let data = CString::new("mode=0755")?;
+    let options = if size_mb > 0 {
+        format!("mode=0755,size={}m", size_mb)
+    } else {
+        "mode=0755".to_string()
+    };
+    let data = CString::new(options)?;
     mount(
         source,
         target,
@@ -366,6 +748,66 @@ pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
     Ok(())
 }
 
+fn is_transient_mount_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let errno = cause
+            .downcast_ref::<rustix::io::Errno>()
+            .map(|e| e.raw_os_error())
+            .or_else(|| {
+                cause
+                    .downcast_ref::<std::io::Error>()
+                    .and_then(|e| e.raw_os_error())
+            });
+        matches!(errno, Some(libc::EBUSY | libc::EAGAIN | libc::ENOMEM))
+    })
+}
+
+pub fn with_thread_limit<T, F>(threads: usize, op: F) -> T
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    if threads == 0 {
+        return op();
+    }
+    match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+        Ok(pool) => pool.install(op),
+        Err(e) => {
+            log::warn!("Failed to build a {threads}-thread pool, using the global one: {e}");
+            op()
+        }
+    }
+}
+
+pub fn retry_mount<T>(
+    what: &str,
+    retries: &MountRetryConfig,
+    mut op: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut backoff = std::time::Duration::from_millis(retries.backoff_ms);
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            // EINVAL, ENOENT and friends are misconfigurations, retrying them only delays boot
+            Err(e) if attempt < retries.attempts.max(1) && is_transient_mount_error(&e) => {
+                log::warn!(
+                    "{} failed (attempt {}/{}): {:#}, retrying in {:?}",
+                    what,
+                    attempt,
+                    retries.attempts,
+                    e,
+                    backoff
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub fn mount_image(image_path: &Path, target: &Path) -> Result<()> {
     ensure_dir_exists(target)?;
     lsetfilecon(image_path, "u:object_r:ksu_file:s0").ok();
@@ -384,20 +826,26 @@ pub fn mount_image(image_path: &Path, target: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn repair_image(image_path: &Path) -> Result<()> {
+// Returns the e2fsck exit code and its combined output, codes above 2 are failures
+pub fn repair_image(image_path: &Path) -> Result<(i32, String)> {
     log::info!("Running e2fsck on {}", image_path.display());
-    let status = Command::new("e2fsck")
+    let output = Command::new("e2fsck")
         .args(["-y", "-f"])
         .arg(image_path)
-        .status()
+        .output()
         .context("Failed to execute e2fsck")?;
 
-    if let Some(code) = status.code()
-        && code > 2
-    {
-        bail!("e2fsck failed with exit code: {}", code);
+    let code = output.status.code().unwrap_or(-1);
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    if !(0..=2).contains(&code) {
+        bail!("e2fsck failed with exit code: {}\n{}", code, text.trim());
     }
-    Ok(())
+    Ok((code, text))
 }
 
 pub fn reflink_or_copy(src: &Path, dest: &Path) -> Result<u64> {
@@ -459,7 +907,48 @@ fn apply_system_context(current: &Path, relative: &Path) -> Result<()> {
     Ok(())
 }
 
-fn native_cp_r(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<()> {
+fn copy_file_metadata(dst: &Path, metadata: &fs::Metadata) -> Result<()> {
+    rustix::fs::chownat(
+        rustix::fs::CWD,
+        dst,
+        Some(rustix::fs::Uid::from_raw(metadata.uid())),
+        Some(rustix::fs::Gid::from_raw(metadata.gid())),
+        rustix::fs::AtFlags::SYMLINK_NOFOLLOW,
+    )?;
+
+    // chown drops setuid/setgid, so the mode has to be restored afterwards
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(dst, metadata.permissions())?;
+    }
+
+    let times = rustix::fs::Timestamps {
+        last_access: rustix::fs::Timespec {
+            tv_sec: metadata.atime(),
+            tv_nsec: metadata.atime_nsec() as _,
+        },
+        last_modification: rustix::fs::Timespec {
+            tv_sec: metadata.mtime(),
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    };
+
+    rustix::fs::utimensat(
+        rustix::fs::CWD,
+        dst,
+        &times,
+        rustix::fs::AtFlags::SYMLINK_NOFOLLOW,
+    )?;
+
+    Ok(())
+}
+
+fn native_cp_r(
+    src: &Path,
+    dst: &Path,
+    relative: &Path,
+    repair: bool,
+    preserve_metadata: bool,
+) -> Result<()> {
     if !dst.exists() {
         if src.is_dir() {
             create_dir_all(dst)?;
@@ -486,7 +975,13 @@ fn native_cp_r(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<
         let ft = metadata.file_type();
 
         if ft.is_dir() {
-            native_cp_r(&src_path, &dst_path, &next_relative, repair)?;
+            native_cp_r(
+                &src_path,
+                &dst_path,
+                &next_relative,
+                repair,
+                preserve_metadata,
+            )?;
         } else if ft.is_symlink() {
             if dst_path.exists() {
                 remove_file(&dst_path)?;
@@ -509,16 +1004,38 @@ fn native_cp_r(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<
         } else {
             let _ = copy_extended_attributes(&src_path, &dst_path);
         }
+
+        // Directories are handled after their children so the copy does not bump their mtime
+        if preserve_metadata && let Err(e) = copy_file_metadata(&dst_path, &metadata) {
+            log::debug!(
+                "Failed to preserve metadata on {}: {}",
+                dst_path.display(),
+                e
+            );
+        }
+    }
+
+    if preserve_metadata
+        && relative.as_os_str().is_empty()
+        && let Ok(src_meta) = src.metadata()
+        && let Err(e) = copy_file_metadata(dst, &src_meta)
+    {
+        log::debug!("Failed to preserve metadata on {}: {}", dst.display(), e);
     }
     Ok(())
 }
 
-pub fn sync_dir(src: &Path, dst: &Path, repair_context: bool) -> Result<()> {
+pub fn sync_dir(
+    src: &Path,
+    dst: &Path,
+    repair_context: bool,
+    preserve_metadata: bool,
+) -> Result<()> {
     if !src.exists() {
         return Ok(());
     }
     ensure_dir_exists(dst)?;
-    native_cp_r(src, dst, Path::new(""), repair_context).with_context(|| {
+    native_cp_r(src, dst, Path::new(""), repair_context, preserve_metadata).with_context(|| {
         format!(
             "Failed to natively sync {} to {}",
             src.display(),
@@ -536,18 +1053,97 @@ fn is_ok_empty<P: AsRef<Path>>(dir: P) -> bool {
         .is_ok_and(|mut entries| entries.next().is_none())
 }
 
-pub fn select_temp_dir() -> Result<PathBuf> {
-    for path_str in TMPFS_CANDIDATES {
-        let path = Path::new(path_str);
-        if is_ok_empty(path) {
+// Dry-run counterpart of the mounting probe: an existing dir takes the tmpfs as is, a missing
+// one must be creatable, so its parent has to be on a writable filesystem
+fn can_host_temp_dir(path: &Path) -> bool {
+    if path.is_dir() {
+        return true;
+    }
+
+    let Some(parent) = path.parent().filter(|p| p.is_dir()) else {
+        return false;
+    };
+
+    let writable_fs =
+        statvfs(parent).is_ok_and(|st| !st.f_flag.contains(StatVfsMountFlags::RDONLY));
+
+    writable_fs && access(parent, Access::WRITE_OK | Access::EXEC_OK).is_ok()
+}
+
+fn probe_temp_dir(path: &Path, check_mount: bool) -> bool {
+    if !check_mount {
+        return can_host_temp_dir(path);
+    }
+
+    let created = !path.exists();
+
+    if ensure_dir_exists(path).is_err() {
+        return false;
+    }
+
+    let usable = mount_tmpfs(path, "tmpfs").is_ok() && {
+        let probe = path.join(".probe");
+        let writable = write(&probe, b"").is_ok();
+        let _ = rustix::mount::unmount(path, rustix::mount::UnmountFlags::DETACH);
+        writable
+    };
+
+    if !usable && created {
+        let _ = fs::remove_dir(path);
+    }
+
+    usable
+}
+
+// A temp root nested in (or containing) storage or overlay workdirs would be shadowed by,
+// or shadow, those mounts
+fn reserved_overlap<'a>(path: &Path, reserved: &[&'a Path]) -> Option<&'a Path> {
+    reserved
+        .iter()
+        .copied()
+        .find(|r| path.starts_with(r) || r.starts_with(path))
+}
+
+pub fn select_temp_dir(
+    configured: Option<&Path>,
+    check_mount: bool,
+    reserved: &[&Path],
+) -> Result<PathBuf> {
+    let mut candidates: Vec<PathBuf> = configured.map(Path::to_path_buf).into_iter().collect();
+
+    candidates.extend(
+        TMPFS_CANDIDATES
+            .iter()
+            .map(Path::new)
+            .filter(|p| is_ok_empty(p))
+            .map(Path::to_path_buf),
+    );
+
+    candidates.extend(
+        defs::TMPFS_FALLBACK_PARENTS
+            .iter()
+            .map(|p| Path::new(p).join(defs::TMPFS_FALLBACK_NAME)),
+    );
+
+    candidates.push(Path::new(defs::RUN_DIR).join("workdir"));
+
+    for path in candidates {
+        if let Some(r) = reserved_overlap(&path, reserved) {
+            log::debug!(
+                "Temp root candidate {} overlaps {}, skipping",
+                path.display(),
+                r.display()
+            );
+            continue;
+        }
+        if probe_temp_dir(&path, check_mount) {
             log::info!("Selected dynamic temp root: {}", path.display());
-            return Ok(path.to_path_buf());
+            return Ok(path);
         }
+        log::debug!("Temp root candidate rejected: {}", path.display());
     }
-    let run_dir = Path::new(defs::RUN_DIR);
-    ensure_dir_exists(run_dir)?;
-    let work_dir = run_dir.join("workdir");
-    Ok(work_dir)
+
+    bail!("No writable, tmpfs-mountable temp directory available")
 }
 
 #[allow(dead_code)]
@@ -634,8 +1230,117 @@ pub fn mount_erofs_image(image_path: &Path, target: &Path) -> Result<()> {
     Ok(())
 }
 
+pub fn http_get(url: &str, timeout_secs: u64) -> Result<Vec<u8>> {
+    let timeout = timeout_secs.to_string();
+
+    let output = match Command::new("curl")
+        .args(["-fsSL", "--max-time", &timeout])
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => Command::new("busybox")
+            .args(["timeout", &timeout, "wget", "-q", "-O", "-"])
+            .arg(url)
+            .stdin(Stdio::null())
+            .output()
+            .context("Neither curl nor busybox wget is available")?,
+    };
+
+    if !output.status.success() {
+        bail!(
+            "HTTP request to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
 pub fn extract_module_id(path: &Path) -> Option<String> {
     path.parent()
         .and_then(|p| p.file_name())
         .map(|s| s.to_string_lossy().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::ScratchDir;
+
+    #[test]
+    fn configured_tempdir_under_reserved_root_is_skipped() {
+        let base = ScratchDir::new("utils-tempdir");
+        let storage = base.join("storage");
+        let configured = storage.join("tmp");
+        create_dir_all(&storage).unwrap();
+
+        assert_eq!(
+            select_temp_dir(Some(&configured), false, &[]).unwrap(),
+            configured
+        );
+        assert_eq!(
+            reserved_overlap(&configured, &[&storage]),
+            Some(storage.as_path())
+        );
+        assert_eq!(
+            reserved_overlap(&base, &[&storage]),
+            Some(storage.as_path())
+        );
+        assert_eq!(reserved_overlap(&base.join("tmp"), &[&storage]), None);
+    }
+
+    // The probe must follow security.selinux alone: a filesystem that takes trusted.* (or
+    // user.*) xattrs but cannot store a label is not good enough for mirrored files
+    #[test]
+    fn xattr_probe_tracks_the_selinux_label_only() {
+        let dir = ScratchDir::new("utils-xattr");
+        let rejecting = |rejected: &'static str| {
+            move |_: &Path, name: &str, _: &[u8]| {
+                if name == rejected {
+                    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        let label_rejected = rejecting(SELINUX_XATTR);
+        let trusted_rejected = rejecting(OVERLAY_TEST_XATTR);
+
+        assert!(!xattr_supported_with(&dir, &label_rejected));
+        assert!(overlay_xattr_supported_with(&dir, &label_rejected).is_err());
+        assert!(xattr_supported_with(&dir, &trusted_rejected));
+        assert!(overlay_xattr_supported_with(&dir, &trusted_rejected).is_ok());
+    }
+
+    #[test]
+    fn sync_dir_preserves_owner_mode_and_mtime() {
+        let base = ScratchDir::new("utils-sync");
+        let src = base.join("src");
+        let dst = base.join("dst");
+        let stamp = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+
+        create_dir_all(src.join("etc")).unwrap();
+        write(src.join("etc/hosts"), b"127.0.0.1 localhost").unwrap();
+        fs::set_permissions(src.join("etc/hosts"), fs::Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(src.join("etc"), fs::Permissions::from_mode(0o750)).unwrap();
+        // Only takes effect as root, otherwise the owner check compares the test user
+        let _ = std::os::unix::fs::chown(src.join("etc/hosts"), Some(1000), Some(1000));
+        for path in [src.join("etc/hosts"), src.join("etc")] {
+            File::open(&path).unwrap().set_modified(stamp).unwrap();
+        }
+        let owner = src.join("etc/hosts").metadata().map(|m| (m.uid(), m.gid()));
+
+        sync_dir(&src, &dst, true, true).unwrap();
+
+        let file_meta = dst.join("etc/hosts").metadata().unwrap();
+        let dir_meta = dst.join("etc").metadata().unwrap();
+        assert_eq!((file_meta.uid(), file_meta.gid()), owner.unwrap());
+        for (meta, mode) in [(file_meta, 0o640), (dir_meta, 0o750)] {
+            assert_eq!(meta.permissions().mode() & 0o7777, mode);
+            assert_eq!(meta.modified().unwrap(), stamp);
+        }
+    }
+}
@@ -5,7 +5,7 @@
 
 import { APP_VERSION } from './constants_gen';
 import { DEFAULT_CONFIG } from './constants';
-import type { AppConfig, DeviceInfo, Module, StorageStatus, SystemInfo, ModuleRules, ConflictEntry, DiagnosticIssue, Silo } from './types';
+import type { AppConfig, ConfigField, DeviceInfo, Module, StorageStatus, SystemInfo, ModuleRules, ModuleRulesPatch, ConflictEntry, DiagnosticIssue, DaemonStage, MountProgress, RatoonStatus, Silo } from './types';
 
 const delay = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
 
@@ -40,6 +40,14 @@ export const MockAPI = {
     await delay(500);
     console.log('[Mock] Config reset to defaults');
   },
+  async getConfigSchema(): Promise<ConfigField[]> {
+    await delay(200);
+    return [
+      { key: 'moduledir', type: 'string', default: '/data/adb/modules', help: 'Directory where modules are installed' },
+      { key: 'verbose', type: 'boolean', default: false, help: 'Enable detailed logging' },
+      { key: 'granary.max_backups', type: 'integer', default: 20, help: 'Silo backups of config and rules, retention_days = 0 keeps them regardless of age' }
+    ];
+  },
   async scanModules(dir: string): Promise<Module[]> {
     await delay(600);
     return [
@@ -50,11 +58,14 @@ export const MockAPI = {
         author: 'Developer',
         description: 'This is a mock module for testing.',
         mode: 'magic',
+        mount_reason: 'module-default:magic',
         is_mounted: true,
         rules: { 
             default_mode: 'magic', 
             paths: { "system/fonts": "overlay" } 
-        }
+        },
+        file_count: 42,
+        total_size_bytes: 18874368
       },
       {
         id: 'overlay_module_2',
@@ -63,11 +74,14 @@ export const MockAPI = {
         author: 'Google',
         description: 'Changes system colors.',
         mode: 'auto',
+        mount_reason: 'default:overlay',
         is_mounted: true,
         rules: { 
             default_mode: 'overlay', 
             paths: {} 
-        }
+        },
+        file_count: 7,
+        total_size_bytes: 524288
       },
       {
         id: 'disabled_module',
@@ -80,7 +94,9 @@ export const MockAPI = {
         rules: {
             default_mode: 'ignore',
             paths: {}
-        }
+        },
+        file_count: 0,
+        total_size_bytes: 0
       }
     ];
   },
@@ -88,6 +104,10 @@ export const MockAPI = {
     await delay(400);
     console.log(`[Mock] Rules saved for ${moduleId}:`, rules);
   },
+  async patchModuleRules(moduleId: string, patch: ModuleRulesPatch): Promise<void> {
+    await delay(300);
+    console.log(`[Mock] Rules merged for ${moduleId}:`, patch);
+  },
   async saveModules(modules: Module[]): Promise<void> {
     console.warn("[Mock] saveModules is deprecated");
   },
@@ -124,6 +144,14 @@ export const MockAPI = {
       hymofs_available: true,
     };
   },
+  async getProgress(): Promise<MountProgress | null> {
+    await delay(100);
+    return null;
+  },
+  async getDaemonStage(): Promise<DaemonStage | null> {
+    await delay(100);
+    return { stage: 'finalized', timestamp: Math.floor(Date.now() / 1000) };
+  },
   async getSystemInfo(): Promise<SystemInfo> {
     await delay(300);
     return {
@@ -177,6 +205,15 @@ export const MockAPI = {
     console.log(`[Mock] Restored silo: ${siloId}`);
   },
 
+  async getRatoonStatus(): Promise<RatoonStatus> {
+    await delay(200);
+    return { count: 0, threshold: 3 };
+  },
+  async resetRatoonCounter(): Promise<void> {
+    await delay(300);
+    console.log('[Mock] Ratoon counter reset');
+  },
+
   async setWinnowingRule(path: string, moduleId: string): Promise<void> {
     await delay(300);
     console.log(`[Mock] Winnow rule set: ${path} -> ${moduleId}`);
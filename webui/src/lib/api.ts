@@ -6,7 +6,7 @@
 import { DEFAULT_CONFIG, PATHS } from './constants';
 import { APP_VERSION } from './constants_gen';
 import { MockAPI } from './api.mock';
-import type { AppConfig, Module, StorageStatus, SystemInfo, DeviceInfo, ModuleRules, ConflictEntry, DiagnosticIssue, Silo } from './types';
+import type { AppConfig, ConfigField, Module, StorageStatus, SystemInfo, DeviceInfo, ModuleRules, ModuleRulesPatch, ConflictEntry, DiagnosticIssue, DaemonStage, MountProgress, RatoonStatus, Silo } from './types';
 
 interface KsuExecResult {
   errno: number;
@@ -29,7 +29,7 @@ try {
 
 const shouldUseMock = import.meta.env.DEV || !ksuExec;
 
-function formatBytes(bytes: number, decimals = 2): string {
+export function formatBytes(bytes: number, decimals = 2): string {
   if (!+bytes) return '0 B';
   const k = 1024;
   const dm = decimals < 0 ? 0 : decimals;
@@ -61,11 +61,14 @@ interface AppAPI {
   loadConfig: () => Promise<AppConfig>;
   saveConfig: (config: AppConfig) => Promise<void>;
   resetConfig: () => Promise<void>;
+  getConfigSchema: () => Promise<ConfigField[]>;
   scanModules: (path?: string) => Promise<Module[]>;
   saveModuleRules: (moduleId: string, rules: ModuleRules) => Promise<void>;
+  patchModuleRules: (moduleId: string, patch: ModuleRulesPatch) => Promise<void>;
   saveModules: (modules: Module[]) => Promise<void>;
   readLogs: (logPath?: string, lines?: number) => Promise<string>;
   getStorageUsage: () => Promise<StorageStatus>;
+  getProgress: () => Promise<MountProgress | null>;
   getSystemInfo: () => Promise<SystemInfo>;
   getDeviceStatus: () => Promise<DeviceInfo>;
   getVersion: () => Promise<string>;
@@ -78,6 +81,8 @@ interface AppAPI {
   createSilo: (reason: string) => Promise<void>;
   deleteSilo: (siloId: string) => Promise<void>;
   restoreSilo: (siloId: string) => Promise<void>;
+  getRatoonStatus: () => Promise<RatoonStatus>;
+  resetRatoonCounter: () => Promise<void>;
   setWinnowingRule: (path: string, moduleId: string) => Promise<void>;
 }
 
@@ -99,8 +104,15 @@ const RealAPI: AppAPI = {
     const jsonStr = JSON.stringify(config);
     const hexPayload = stringToHex(jsonStr);
     const cmd = `${PATHS.BINARY} save-config --payload ${hexPayload}`;
-    const { errno, stderr } = await ksuExec(cmd);
-    if (errno !== 0) throw new Error(`Failed to save config: ${stderr}`);
+    const { errno, stdout, stderr } = await ksuExec(cmd);
+    if (errno !== 0) {
+      let reason = stderr;
+      try {
+        const result = JSON.parse(stdout);
+        if (result && result.message) reason = result.message;
+      } catch (e) {}
+      throw new Error(`Failed to save config: ${reason}`);
+    }
   },
   resetConfig: async (): Promise<void> => {
     if (!ksuExec) throw new Error("No KSU environment");
@@ -108,6 +120,15 @@ const RealAPI: AppAPI = {
     const { errno, stderr } = await ksuExec(cmd);
     if (errno !== 0) throw new Error(`Failed to reset config: ${stderr}`);
   },
+  getConfigSchema: async (): Promise<ConfigField[]> => {
+    if (!ksuExec) return [];
+    const cmd = `${PATHS.BINARY} config-schema`;
+    try {
+      const { errno, stdout } = await ksuExec(cmd);
+      if (errno === 0 && stdout) return JSON.parse(stdout);
+    } catch (e) {}
+    return [];
+  },
   scanModules: async (path?: string): Promise<Module[]> => {
     if (!ksuExec) return [];
     const cmd = `${PATHS.BINARY} modules`;
@@ -125,11 +146,21 @@ const RealAPI: AppAPI = {
     const { errno, stderr } = await ksuExec(cmd);
     if (errno !== 0) throw new Error(`Failed to save rules: ${stderr}`);
   },
+  patchModuleRules: async (moduleId: string, patch: ModuleRulesPatch): Promise<void> => {
+    if (!ksuExec) throw new Error("No KSU environment");
+    const jsonStr = JSON.stringify(patch);
+    const hexPayload = stringToHex(jsonStr);
+    const cmd = `${PATHS.BINARY} save-rules --merge --module "${moduleId}" --payload "${hexPayload}"`;
+    const { errno, stderr } = await ksuExec(cmd);
+    if (errno !== 0) throw new Error(`Failed to save rules: ${stderr}`);
+  },
   saveModules: async (modules: Module[]): Promise<void> => { return; },
   readLogs: async (logPath?: string, lines = 1000): Promise<string> => {
     if (!ksuExec) return "";
     const f = logPath || (PATHS as any).DAEMON_LOG || "/data/adb/meta-hybrid/daemon.log";
-    const cmd = `[ -f "${f}" ] && tail -n ${lines} "${f}" || echo ""`;
+    const cmd = logPath
+      ? `[ -f "${f}" ] && tail -n ${lines} "${f}" || echo ""`
+      : `${PATHS.BINARY} log --lines ${lines}`;
     const { errno, stdout, stderr } = await ksuExec(cmd);
     if (errno === 0) return stdout || "";
     throw new Error(stderr || "Log file not found");
@@ -152,6 +183,27 @@ const RealAPI: AppAPI = {
     } catch (e) {}
     return { size: '-', used: '-', percent: '0%', type: null };
   },
+  getProgress: async (): Promise<MountProgress | null> => {
+    if (!ksuExec) return null;
+    try {
+      const progressFile = (PATHS as any).PROGRESS || "/data/adb/meta-hybrid/progress.json";
+      const { errno, stdout } = await ksuExec(`[ -f "${progressFile}" ] && cat "${progressFile}"`);
+      if (errno === 0 && stdout) return JSON.parse(stdout);
+    } catch (e) {}
+    return null;
+  },
+  getDaemonStage: async (): Promise<DaemonStage | null> => {
+    if (!ksuExec) return null;
+    try {
+      const stageFile = PATHS.STAGE || "/data/adb/meta-hybrid/stage";
+      const { errno, stdout } = await ksuExec(`[ -f "${stageFile}" ] && cat "${stageFile}"`);
+      if (errno === 0 && stdout) {
+        const [stage, timestamp] = stdout.trim().split(/\s+/);
+        return { stage: stage as DaemonStage['stage'], timestamp: parseInt(timestamp, 10) || 0 };
+      }
+    } catch (e) {}
+    return null;
+  },
   getSystemInfo: async (): Promise<SystemInfo> => {
     if (!ksuExec) return { kernel: '-', selinux: '-', mountBase: '-', activeMounts: [] };
     try {
@@ -261,13 +313,27 @@ const RealAPI: AppAPI = {
   },
   deleteSilo: async (siloId: string): Promise<void> => {
     if (!ksuExec) return;
-    const cmd = `${PATHS.BINARY} system-action --action granary-delete --value "${siloId}"`;
+    const cmd = `${PATHS.BINARY} system-action --action granary-delete --value "${siloId}" --confirm`;
     const { errno, stderr } = await ksuExec(cmd);
     if (errno !== 0) throw new Error(stderr);
   },
   restoreSilo: async (siloId: string): Promise<void> => {
     if (!ksuExec) return;
-    const cmd = `${PATHS.BINARY} system-action --action granary-restore --value "${siloId}"`;
+    const cmd = `${PATHS.BINARY} system-action --action granary-restore --value "${siloId}" --confirm`;
+    const { errno, stderr } = await ksuExec(cmd);
+    if (errno !== 0) throw new Error(stderr);
+  },
+  getRatoonStatus: async (): Promise<RatoonStatus> => {
+    if (!ksuExec) return { count: 0, threshold: 3 };
+    try {
+        const { errno, stdout } = await ksuExec(`${PATHS.BINARY} system-action --action granary-ratoon`);
+        if (errno === 0 && stdout) return JSON.parse(stdout);
+    } catch {}
+    return { count: 0, threshold: 3 };
+  },
+  resetRatoonCounter: async (): Promise<void> => {
+    if (!ksuExec) return;
+    const cmd = `${PATHS.BINARY} system-action --action granary-reset-counter`;
     const { errno, stderr } = await ksuExec(cmd);
     if (errno !== 0) throw new Error(stderr);
   },
@@ -17,7 +17,8 @@ export const DEFAULT_CONFIG: AppConfig = {
   allow_umount_coexistence: false,
   dry_run: false,
   winnowing: {
-    rules: ''
+    strategy: 'first',
+    rules: {}
   },
   granary: {
     max_backups: 20,
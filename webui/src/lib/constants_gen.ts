@@ -11,5 +11,7 @@ export const RUST_PATHS = {
   IMAGE_MNT: "/data/adb/meta-hybrid/mnt",
   DAEMON_STATE: "/data/adb/meta-hybrid/run/daemon_state.json",
   DAEMON_LOG: "/data/adb/meta-hybrid/daemon.log",
+  PROGRESS: "/data/adb/meta-hybrid/progress.json",
+  STAGE: "/data/adb/meta-hybrid/stage",
 } as const;
 export const BUILTIN_PARTITIONS = ["system", "vendor", "product", "system_ext", "odm", "oem", "apex"] as const;
\ No newline at end of file
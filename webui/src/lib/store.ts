@@ -18,7 +18,8 @@ import type {
   LanguageOption,
   ModeStats,
   ConflictEntry,
-  DiagnosticIssue
+  DiagnosticIssue,
+  MountProgress
 } from './types';
 
 const localeModules = import.meta.glob('../locales/*.json', { eager: true });
@@ -55,6 +56,8 @@ const createGlobalStore = () => {
   const [activePartitions, setActivePartitions] = createSignal<string[]>([]);
   const [conflicts, setConflicts] = createSignal<ConflictEntry[]>([]);
   const [diagnostics, setDiagnostics] = createSignal<DiagnosticIssue[]>([]);
+  const [progress, setProgress] = createSignal<MountProgress | null>(null);
+  let progressTimer: ReturnType<typeof setInterval> | null = null;
   
   const [loadingConfig, setLoadingConfig] = createSignal(false);
   const [loadingModules, setLoadingModules] = createSignal(false);
@@ -273,6 +276,23 @@ const createGlobalStore = () => {
     setLoadingStatus(false);
   }
 
+  async function pollProgress() {
+    const p = await API.getProgress();
+    setProgress(p);
+    if (!p && progressTimer) {
+      clearInterval(progressTimer);
+      progressTimer = null;
+      await loadStatus();
+    }
+  }
+
+  async function watchProgress() {
+    await pollProgress();
+    if (progress() && !progressTimer) {
+      progressTimer = setInterval(pollProgress, 1000);
+    }
+  }
+
   async function loadConflicts() {
       setLoadingConflicts(true);
       try {
@@ -331,6 +351,9 @@ const createGlobalStore = () => {
     
     get diagnostics() { return diagnostics(); },
     
+    get progress() { return progress(); },
+    watchProgress,
+    
     loadStatus,
     
     get loading() {
@@ -8,7 +8,14 @@ export interface GranaryConfig {
   retention_days: number;
 }
 
-export type OverlayMode = 'tmpfs' | 'ext4' | 'erofs';
+export type WinnowingStrategy = 'first' | 'last' | 'alphabetical';
+
+export interface WinnowingConfig {
+  strategy: WinnowingStrategy;
+  rules: Record<string, string>;
+}
+
+export type OverlayMode = 'tmpfs' | 'ext4' | 'erofs' | 'auto';
 
 export interface AppConfig {
   moduledir: string;
@@ -22,10 +29,19 @@ export interface AppConfig {
   allow_umount_coexistence: boolean;
   dry_run: boolean;
   logfile?: string;
-  winnowing?: Record<string, string>;
+  winnowing?: WinnowingConfig;
   granary: GranaryConfig;
 }
 
+export type ConfigFieldType = 'boolean' | 'integer' | 'number' | 'string' | 'array' | 'table' | 'null';
+
+export interface ConfigField {
+  key: string;
+  type: ConfigFieldType;
+  default: unknown;
+  help: string;
+}
+
 export type MountMode = 'overlay' | 'magic' | 'ignore';
 
 export interface ModuleRules {
@@ -33,15 +49,25 @@ export interface ModuleRules {
   paths: Record<string, MountMode>;
 }
 
+export interface ModuleRulesPatch {
+  default_mode?: MountMode;
+  paths?: Record<string, MountMode | null>;
+}
+
 export interface Module {
   id: string;
   name: string;
   version: string;
+  version_code?: number;
   author: string;
   description: string;
+  update_json?: string;
   mode: string;
+  mount_reason?: string;
   is_mounted: boolean;
   rules: ModuleRules;
+  file_count?: number;
+  total_size_bytes?: number;
   enabled?: boolean;
   source_path?: string;
 }
@@ -94,11 +120,13 @@ export interface ConflictEntry {
   contending_modules: string[];
   selected?: string;
   is_forced?: boolean;
+  resolved_by?: string;
 }
 
 export interface Silo {
   id: string;
   timestamp: number;
+  sequence?: number;
   label: string;
   reason: string;
   config_snapshot: AppConfig;
@@ -106,6 +134,25 @@ export interface Silo {
   raw_state?: string;
 }
 
+export interface MountProgress {
+  phase: 'sync' | 'overlay' | 'magic';
+  module: string | null;
+  target: string | null;
+  done: number;
+  total: number;
+  timestamp: number;
+}
+
+export interface DaemonStage {
+  stage: 'init' | 'storage-ready' | 'mounted' | 'failed' | 'finalized';
+  timestamp: number;
+}
+
+export interface RatoonStatus {
+  count: number;
+  threshold: number;
+}
+
 export interface DiagnosticIssue {
   level: 'Info' | 'Warning' | 'Critical';
   context: string;
@@ -110,13 +110,14 @@ export default function ConfigTab() {
   }
 
   const availableModes = createMemo(() => 
-    store.systemInfo?.supported_overlay_modes ?? (['tmpfs', 'ext4', 'erofs'] as OverlayMode[])
+    store.systemInfo?.supported_overlay_modes ?? (['tmpfs', 'ext4', 'erofs', 'auto'] as OverlayMode[])
   );
 
   const MODE_DESCS: Record<OverlayMode, string> = {
     'tmpfs': 'RAM-based. Fastest I/O, reset on reboot.',
     'ext4': 'Loopback image. Persistent, saves RAM.',
-    'erofs': 'Read-only compressed. High performance, space saving.'
+    'erofs': 'Read-only compressed. High performance, space saving.',
+    'auto': 'tmpfs, or ext4 when available RAM is low.'
   };
 
   return (
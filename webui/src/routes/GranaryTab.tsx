@@ -6,7 +6,7 @@
 import { createSignal, createMemo, onMount, Show, For } from 'solid-js';
 import { API } from '../lib/api';
 import { store } from '../lib/store';
-import type { Silo } from '../lib/types';
+import type { RatoonStatus, Silo } from '../lib/types';
 import Skeleton from '../components/Skeleton';
 import BottomActions from '../components/BottomActions';
 import './GranaryTab.css';
@@ -32,6 +32,7 @@ export default function GranaryTab() {
   const [selectedSilo, setSelectedSilo] = createSignal<Silo | null>(null);
   const [newSiloReason, setNewSiloReason] = createSignal("");
   const [isCreating, setIsCreating] = createSignal(false);
+  const [ratoon, setRatoon] = createSignal<RatoonStatus>({ count: 0, threshold: 3 });
 
   const I_RESTORE = "M13,3A9,9 0 0,0 4,12H1L4.89,15.89L4.96,16.03L9,12H6A7,7 0 0,1 13,5A7,7 0 0,1 20,12A7,7 0 0,1 13,19C11.07,19 9.32,18.2 8.06,16.94L6.64,18.36C8.27,20 10.5,21 13,21A9,9 0 0,0 22,12A9,9 0 0,0 13,3Z";
   const I_DELETE = "M19,4H15.5L14.5,3H9.5L8.5,4H5V6H19M6,19A2,2 0 0,0 8,21H16A2,2 0 0,0 18,19V7H6V19Z";
@@ -48,6 +49,7 @@ export default function GranaryTab() {
     try {
       const list = await API.getGranaryList();
       setSilos(list);
+      setRatoon(await API.getRatoonStatus());
     } catch (e) {
       store.showToast(L_G().loadError || "Failed to load Granary", "error");
     } finally {
@@ -98,6 +100,16 @@ export default function GranaryTab() {
     }
   }
 
+  async function resetRatoon() {
+    try {
+      await API.resetRatoonCounter();
+      setRatoon({ ...ratoon(), count: 0 });
+      store.showToast(L_G().ratoonResetSuccess || "Boot counter reset", "success");
+    } catch (e: any) {
+      store.showToast(e.message, "error");
+    }
+  }
+
   function openCreateDialog() {
       setNewSiloReason("");
       setShowCreateDialog(true);
@@ -203,6 +215,20 @@ export default function GranaryTab() {
       </md-dialog>
 
       <div class="granary-list">
+        <Show when={ratoon().count > 0}>
+          <div class="silo-card ratoon-warning">
+            <div class="silo-title">
+              {(L_G().ratoonWarning || '⚠️ {count} unsuccessful boots detected, {left} more will trigger rollback.')
+                .replace('{count}', String(ratoon().count))
+                .replace('{left}', String(Math.max(ratoon().threshold - ratoon().count, 0)))}
+            </div>
+            <div class="card-actions">
+              <md-filled-tonal-button onClick={resetRatoon}>
+                {L_G().ratoonReset || 'Reset Counter'}
+              </md-filled-tonal-button>
+            </div>
+          </div>
+        </Show>
         <Show when={!loading()} fallback={
           <For each={Array(3)}>{() =>
             <div class="silo-card">
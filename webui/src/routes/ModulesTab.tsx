@@ -8,7 +8,7 @@ import { store } from '../lib/store';
 import { ICONS } from '../lib/constants';
 import Skeleton from '../components/Skeleton';
 import BottomActions from '../components/BottomActions';
-import { API } from '../lib/api';
+import { API, formatBytes } from '../lib/api';
 import type { Module, MountMode } from '../lib/types';
 import './ModulesTab.css';
 import '@material/web/iconbutton/filled-tonal-icon-button.js';
@@ -319,6 +319,13 @@ export default function ModulesTab() {
                       <div class="rule-details">
                         <p class="module-desc">{mod.description || (store.L.modules?.noDesc ?? 'No description')}</p>
                         <p class="module-meta">{store.L.modules?.author ?? 'Author'}: {mod.author || (store.L.modules?.unknown ?? 'Unknown')}</p>
+                        <Show when={mod.file_count !== undefined}>
+                          <p class="module-meta">
+                            {(store.L.modules?.footprint ?? 'Footprint: {count} files, {size}')
+                              .replace('{count}', String(mod.file_count ?? 0))
+                              .replace('{size}', formatBytes(mod.total_size_bytes ?? 0))}
+                          </p>
+                        </Show>
                         
                         <Show when={!mod.is_mounted}>
                               <div class="status-alert">
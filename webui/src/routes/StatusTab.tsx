@@ -23,6 +23,7 @@ import '@material/web/button/text-button.js';
 export default function StatusTab() {
   onMount(() => {
     store.loadStatus();
+    store.watchProgress();
   });
 
   const displayPartitions = createMemo(() => 
@@ -56,6 +57,14 @@ export default function StatusTab() {
     return parseFloat(store.storage.percent) / 100;
   }
 
+  function getProgressLabel() {
+    const p = store.progress;
+    if (!p) return '';
+    const phase = store.L?.status?.progressPhase?.[p.phase] ?? p.phase;
+    const item = p.module ?? p.target;
+    return item ? `${phase}: ${item}` : phase;
+  }
+
   function getStorageBadgeClass(type: string | null | undefined) {
     if (type === 'tmpfs') return 'type-tmpfs';
     if (type === 'ext4') return 'type-ext4';
@@ -139,6 +148,21 @@ export default function StatusTab() {
           </Show>
         </div>
 
+        <Show when={store.progress}>
+          {(p) => (
+            <div class="mode-card">
+              <div class="mode-title">{store.L?.status?.progressTitle ?? 'Mounting'}</div>
+              <div class="progress-container">
+                <md-linear-progress value={p().total ? p().done / p().total : 0}></md-linear-progress>
+              </div>
+              <div class="storage-details">
+                <span class="detail-path">{getProgressLabel()}</span>
+                <span class="detail-nums">{p().done} / {p().total}</span>
+              </div>
+            </div>
+          )}
+        </Show>
+
         <div class="stats-row">
           <div class="stat-card">
             <Show when={!store.loading.status} fallback={
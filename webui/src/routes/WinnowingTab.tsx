@@ -51,7 +51,7 @@ export default function WinnowingTab() {
     const idx = list.findIndex(c => c.partition === item.partition && c.relative_path === item.relative_path);
     if (idx !== -1) {
       const newList = [...list];
-      newList[idx] = { ...newList[idx], selected: moduleId, is_forced: true };
+      newList[idx] = { ...newList[idx], selected: moduleId, is_forced: true, resolved_by: 'rule' };
       setConflicts(newList);
     }
     try {